@@ -12,6 +12,9 @@ sleep 2
 echo "=== Загружаем новый бинарник ==="
 scp server/target/release/keldurben-server ${VDS_USER}@${VDS_IP}:${VDS_PATH}/target/release/
 
+echo "=== Применяем миграции БД ==="
+ssh ${VDS_USER}@${VDS_IP} "cd ${VDS_PATH} && env DATABASE_URL=sqlite:///opt/keldurben/app/data/keldurben.db ./target/release/keldurben-server --migrate-only" || exit 1
+
 echo "=== Запускаем новый сервер ==="
 ssh ${VDS_USER}@${VDS_IP} "cd ${VDS_PATH} && nohup env DATABASE_URL=sqlite:///opt/keldurben/app/data/keldurben.db STATIC_DIR=/opt/keldurben/app/frontend JWT_SECRET=\$(openssl rand -hex 32) ADMIN_SECRET=\$(openssl rand -hex 32) BIND=0.0.0.0:8765 ./target/release/keldurben-server >/var/log/keldurben.log 2>&1 & echo \$! > /var/run/keldurben.pid"
 
@@ -19,6 +22,7 @@ sleep 3
 
 echo "=== Проверяем запуск ==="
 ssh ${VDS_USER}@${VDS_IP} "ss -lntp | grep 8765"
+ssh ${VDS_USER}@${VDS_IP} "curl -fsS http://127.0.0.1:8765/readyz" || echo "⚠️  /readyz не отвечает 200"
 ssh ${VDS_USER}@${VDS_IP} "tail -n 20 /var/log/keldurben.log"
 
 echo ""
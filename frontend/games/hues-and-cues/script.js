@@ -57,10 +57,9 @@
   // Всегда прячем локальную секцию добавления игроков (онлайн-режим только)
   if (addPlayerSectionEl) addPlayerSectionEl.style.display = 'none';
 
-  // Configurable board size via CSS variables
-  const COLS = 30;
-  const ROWS = 18;
-  const WIN_SCORE = 15; // победа при достижении этого количества очков
+  // Размер доски: 30×18 до первого ответа сервера, дальше — из описания доски (см. loadBoardPalette)
+  let COLS = 30;
+  let ROWS = 18;
 
   /** @typedef {{ id:string, name:string, score:number }} Player */
   /** @typedef {{ round:number, cueGiverIndex:number, targetIndex:number|null, cue:string|null, guesses:Record<string, number|null>, phase:'setup'|'cue'|'guess'|'reveal' }} GameState */
@@ -82,6 +81,7 @@
   let prevServerCue1 = null;
   let prevServerTarget = null;
   let prevPlayersMap = new Map(); // id -> name, для логов присоединения/выхода
+  let loggedResultRound = null; // раунд, итоги которого уже записаны в лог
   // Локальная подсветка выбранного индекса до того, как сервер пришлёт target (только для дающего)
   let localSelectedIdx = null;
   let localSelectedRound = null;
@@ -103,6 +103,8 @@
     // Верхние и нижние цифры (1-30)
     const coordNumbersTop = document.querySelector('.coordinates-top .coord-numbers');
     const coordNumbersBottom = document.querySelector('.coordinates-bottom .coord-numbers');
+    coordNumbersTop.innerHTML = '';
+    coordNumbersBottom.innerHTML = '';
     
     for (let c = 0; c < COLS; c++) {
       const number = document.createElement('div');
@@ -115,6 +117,8 @@
     // Левые и правые буквы (A-R)
     const coordLettersLeft = document.querySelector('.coordinates-left .coord-letters');
     const coordLettersRight = document.querySelector('.coordinates-right .coord-letters');
+    coordLettersLeft.innerHTML = '';
+    coordLettersRight.innerHTML = '';
     
     for (let r = 0; r < ROWS; r++) {
       const letter = document.createElement('div');
@@ -125,6 +129,35 @@
     }
   }
 
+  // Палитра доски с сервера: цвета клеток задаёт сервер по board_id, локальный HSL — только до первого состояния
+  let boardId = null;
+  async function loadBoardPalette(id) {
+    boardId = id;
+    try {
+      const base = FIXED_WS_URL.replace(/^ws/, 'http').replace(/\/ws$/, '');
+      const res = await fetch(`${base}/api/boards/${encodeURIComponent(id)}`);
+      if (!res.ok) throw new Error(`HTTP ${res.status}`);
+      const palette = await res.json();
+      if (boardId !== id) return;
+      if (palette.cols !== COLS || palette.rows !== ROWS) resizeBoard(palette.cols, palette.rows);
+      palette.cells.forEach((color, i) => { cells[i].style.background = color; });
+    } catch (e) {
+      console.warn('Не удалось загрузить палитру доски', id, e);
+      if (boardId === id) boardId = null;
+    }
+  }
+
+  // Перестраивает сетку под размер доски сервера; CSS берёт его из --cols/--rows
+  function resizeBoard(cols, rows) {
+    COLS = cols;
+    ROWS = rows;
+    const container = document.querySelector('.board-container');
+    container?.style.setProperty('--cols', String(cols));
+    container?.style.setProperty('--rows', String(rows));
+    createCoordinates();
+    generateBoard();
+  }
+
   // Build color board: HSL grid for good coverage
   function generateBoard() {
     console.log('=== GENERATING BOARD ===');
@@ -354,7 +387,9 @@
         row.classList.add('current-player');
       }
       const left = document.createElement('div');
-      left.textContent = p.name + (i === state.cueGiverIndex && state.phase !== 'setup' ? ' (даёт подсказку)' : '');
+      const inLobby = ['lobby', 'game_over'].includes(window.__serverState?.phase);
+      left.textContent = p.name + (p.bot ? ' 🤖' : '') + (p.friend ? ' 👥' : '') + (inLobby && p.ready ? ' ✅' : '') + (i === state.cueGiverIndex && state.phase !== 'setup' ? ' (даёт подсказку)' : '');
+      if (p.friend) left.title = 'Друг';
       const right = document.createElement('div');
       right.className = 'score';
       right.textContent = String(p.score);
@@ -375,7 +410,12 @@
     console.log('updateUIState called, phase:', phase);
     
     if (phase === 'lobby' || phase === 'setup') {
-      roundInfoEl.textContent = online ? 'Ожидание игроков. Нажмите Старт.' : 'Добавьте игроков и нажмите Старт.';
+      const limits = s?.player_limits;
+      const blockers = Array.isArray(s?.start_blockers) ? s.start_blockers : [];
+      const waiting = blockers.includes('not enough players')
+        ? `Нужно минимум ${limits?.min ?? 2} игрока (${players.length}/${limits?.max ?? '?'}).`
+        : blockers.includes('not everyone is ready') ? 'Ждём, пока все нажмут «Готов».' : 'Все готовы. Нажмите Старт.';
+      roundInfoEl.textContent = online ? waiting : 'Добавьте игроков и нажмите Старт.';
       cueArea1El.classList.add('hidden');
       cueArea2El.classList.add('hidden');
       currentCueEl.classList.add('hidden');
@@ -468,7 +508,12 @@
 
   function startGame() {
     if (isOnline()) {
-      wsSend({ type: 'start_game' });
+      // Хост может начать, не дожидаясь готовности всех
+      const s = window.__serverState;
+      const isHost = s && selfId && s.host === selfId;
+      const onlyNotReady = s && Array.isArray(s.start_blockers) && s.start_blockers.length === 1 && s.start_blockers[0] === 'not everyone is ready';
+      const force = Boolean(isHost && onlyNotReady && confirm('Не все готовы. Начать всё равно?'));
+      wsSend({ type: 'start_game', force });
     } else {
       startOfflineGame();
     }
@@ -560,6 +605,12 @@
     return Math.abs(a.r - b.r) + Math.abs(a.c - b.c);
   }
 
+  function chebyshev(aIdx, bIdx) {
+    const a = idxToRC(aIdx);
+    const b = idxToRC(bIdx);
+    return Math.max(Math.abs(a.r - b.r), Math.abs(a.c - b.c));
+  }
+
   function scoreByDistance(d) {
     if (d === 0) return 3;
     if (d === 1) return 2;
@@ -632,6 +683,13 @@
   });
 
   startGameBtn.addEventListener('click', startGame);
+  const addBotBtn = document.getElementById('addBotBtn');
+  if (addBotBtn) addBotBtn.addEventListener('click', () => wsSend({ type: 'add_bot', difficulty: 'medium', lang: 'ru' }));
+  const readyBtn = document.getElementById('readyBtn');
+  if (readyBtn) readyBtn.addEventListener('click', () => {
+    const me = (window.__serverState?.players || []).find(p => p.id === selfId);
+    wsSend({ type: 'set_ready', ready: !(me && me.ready) });
+  });
   lockCueBtn1.addEventListener('click', lockCue1);
   lockCueBtn2.addEventListener('click', lockCue2);
   // Добавляем обработчик для кнопки "Следующий раунд" с дополнительной отладкой
@@ -685,7 +743,8 @@
         const cu = JSON.parse(localStorage.getItem('currentUser') || 'null');
         if (cu && cu.username) uname = cu.username;
       } catch {}
-      wsSend({ type: 'join', name: (selfNameInput?.value || uname).trim(), room: ROOM_NAME });
+      const token = localStorage.getItem('authToken') || undefined;
+      wsSend({ type: 'join', name: (selfNameInput?.value || uname).trim(), room: ROOM_NAME, token });
       if (connectBtn) { connectBtn.textContent = 'Отключиться'; connectBtn.onclick = wsDisconnect; }
       if (startGameBtn) startGameBtn.disabled = false;
       modalBlockedUntilStart = true;
@@ -696,6 +755,8 @@
         if (msg.type === 'welcome') { selfId = msg.id; }
         if (msg.type === 'state') { applyServerState(msg.state); }
         if (msg.type === 'error') { alert(msg.message); }
+        // Устаревший ход (двойной клик, запоздалое сообщение) молча отбрасываем
+        if (msg.type === 'reject' && msg.reason !== 'stale command') { alert(msg.reason); }
       } catch (e) {
         console.error(e);
       }
@@ -709,7 +770,25 @@
     };
   }
   function wsDisconnect() { if (ws) ws.close(); }
-  function wsSend(obj) { if (ws && ws.readyState === WebSocket.OPEN) ws.send(JSON.stringify(obj)); }
+  // Каждое сообщение нумеруется (сервер отвечает ack/reject и не выполняет повтор), а ходы в игре
+  // привязаны к раунду и фазе: двойной клик по «Следующий раунд» не пропустит дающего подсказку
+  const ROUND_BOUND = new Set(['choose_target', 'lock_cue1', 'lock_cue2', 'guess', 'next_round']);
+  let msgSeq = 0;
+  // Номер вкладки: переживает переподключение и перезагрузку, но у другой вкладки свой
+  const SESSION_ID = (() => {
+    try {
+      let id = sessionStorage.getItem('huesSession');
+      if (!id) { id = Math.random().toString(36).slice(2) + Date.now().toString(36); sessionStorage.setItem('huesSession', id); }
+      return id;
+    } catch { return Math.random().toString(36).slice(2); }
+  })();
+  function wsSend(obj) {
+    if (!(ws && ws.readyState === WebSocket.OPEN)) return;
+    const msg = { ...obj, msg_id: `${Date.now().toString(36)}-${++msgSeq}`, session: SESSION_ID };
+    const s = window.__serverState;
+    if (s && ROUND_BOUND.has(obj.type)) { msg.expect_round = s.round; msg.expect_phase = s.phase; }
+    ws.send(JSON.stringify(msg));
+  }
 
   // Экспортируем общий дисконнект для хаба
   try { window.disconnectActiveGame = () => { try { wsDisconnect(); } catch {} }; } catch {}
@@ -749,6 +828,7 @@
 
   function applyServerState(s) {
     window.__serverState = s;
+    if (s.board_id && s.board_id !== boardId) loadBoardPalette(s.board_id);
     // Не показывать модалку при самом первом подключении (только если это лобби):
     if (prevServerRound == null && (s.phase === 'lobby' || s.phase === 'setup' || s.round === 0)) {
       modalShownRound = s.round;
@@ -856,11 +936,27 @@
     if (s.phase === 'reveal' && typeof s.target === 'number') {
       const targetIdx = s.target;
       cells[targetIdx]?.classList.add('target');
-      // Показать очки для всех клеток в радиусе Манхэттена <= 2 (3/2/1)
-      for (let i = 0; i < cells.length; i++) {
-        const d = manhattan(i, targetIdx);
-        const pts = scoreByDistance(d);
-        if (pts > 0) cells[i]?.setAttribute('data-points', String(pts));
+      // Показать очки вокруг цели (3/2/1) по правилу подсчёта комнаты; при ΔE зоны зависят от цвета, а не от клеток
+      if (s.scoring !== 'delta_e') {
+        const distance = s.scoring === 'frame' ? chebyshev : manhattan;
+        for (let i = 0; i < cells.length; i++) {
+          const pts = scoreByDistance(distance(i, targetIdx));
+          if (pts > 0) cells[i]?.setAttribute('data-points', String(pts));
+        }
+      }
+      // Очки за раунд — от сервера
+      const result = s.round_result;
+      if (result && result.round !== loggedResultRound) {
+        loggedResultRound = result.round;
+        for (const pr of result.players || []) {
+          const p = players.find(x => x.id === pr.player);
+          if (!p) continue;
+          if (pr.player === result.cue_giver) { log(`${p.name}: +${pr.points} (давал подсказку)`); continue; }
+          if (typeof pr.guess !== 'number') { log(`${p.name}: не угадывал`); continue; }
+          const cell = `${String.fromCharCode(65 + Math.floor(pr.guess / COLS))}${(pr.guess % COLS) + 1}`;
+          const which = pr.guess_used === 'first' ? 'первая догадка' : 'вторая догадка';
+          log(`${p.name}: ${cell} (${which}), расстояние ${pr.distance}, +${pr.points} очков`);
+        }
       }
     }
     // Логи по изменениям состояния
@@ -893,12 +989,15 @@
     prevServerTarget = s.target;
     updateUIState();
 
-    // Выявление победителя (>= WIN_SCORE) и показ модалки один раз
+    // Конец игры решает сервер (фаза game_over с итоговой таблицей); показываем модалку один раз
     try {
-      const winner = Array.isArray(players) ? players.find(p => (p.score || 0) >= WIN_SCORE) : null;
+      const standings = s.phase === 'game_over' && Array.isArray(s.standings) ? s.standings : null;
+      const winner = standings ? standings[0] : null;
       if (winner && !winnerShown && !suppressWinnerModal) {
-        if (winnerTitleEl) winnerTitleEl.textContent = 'Победитель';
-        if (winnerTextEl) winnerTextEl.textContent = `${winner.name} набрал ${WIN_SCORE} очков и победил!`;
+        const winners = standings.filter(st => st.place === 1).map(st => st.name);
+        if (winnerTitleEl) winnerTitleEl.textContent = winners.length > 1 ? 'Ничья' : 'Победитель';
+        if (winnerTextEl) winnerTextEl.textContent = `${winners.join(', ')}: ${winner.score} очков. `
+          + standings.map(st => `${st.place}. ${st.name} — ${st.score}`).join('; ');
         if (winnerModalEl) winnerModalEl.classList.remove('hidden');
         winnerShown = true;
       }
@@ -951,7 +1050,9 @@
       modalBlockedUntilStart = false; // разрешаем модалку выбора после явного старта
       suppressWinnerModal = true; // не показывать победителя, пока ждём подтверждение от сервера
       if (isOnline()) {
-        wsSend({ type: 'start_game' });
+        // После конца игры — реванш тем же составом и с теми же настройками
+        const over = window.__serverState && window.__serverState.phase === 'game_over';
+        wsSend({ type: over ? 'rematch' : 'start_game' });
       } else {
         // Если не подключены — подключаемся и после открытия сокета шлём start_game
         wsConnect();
@@ -631,13 +631,20 @@
     const dropdownStatus = dropdown.querySelector('.dropdown-status');
     
     if (user && user.username) {
-      const avatar = user.avatar ? avatarOptions.find(a => a.id === user.avatar) : generateAvatarFromUsername(user.username);
-      avatarElement.innerHTML = `<div class="avatar-emoji">${avatar.emoji}</div>`;
+      let avatarHtml;
+      if (user.avatar && user.avatar.startsWith('/')) {
+        // Загруженный аватар лежит на сервере
+        avatarHtml = `<img class="avatar-image" src="${API_BASE_URL.replace(/\/api$/, '')}${user.avatar}" alt="">`;
+      } else {
+        const avatar = (user.avatar && avatarOptions.find(a => a.id === user.avatar)) || generateAvatarFromUsername(user.username);
+        avatarHtml = `<div class="avatar-emoji">${avatar.emoji}</div>`;
+      }
+      avatarElement.innerHTML = avatarHtml;
       textElement.textContent = user.username;
       profileBtn.classList.add('logged-in');
       
       // Обновляем dropdown
-      dropdownAvatar.innerHTML = `<div class="avatar-emoji">${avatar.emoji}</div>`;
+      dropdownAvatar.innerHTML = avatarHtml;
       dropdownUsername.textContent = user.username;
       dropdownStatus.textContent = 'Онлайн';
       dropdownStatus.classList.remove('offline');
@@ -0,0 +1,125 @@
+//! The Hues and Cues board: which colour every cell is. Rooms carry a board id;
+//! clients fetch the palette for it from `/api/boards/{id}` instead of drawing their own.
+
+use serde::{Deserialize, Serialize};
+
+use crate::color::{hsl_to_rgb, oklch_to_rgb, rgb_to_lab, Lab, Rgb};
+
+/// Never change a released gradient's formula: board ids (and anything recorded with them) would change meaning.
+/// Add a new variant instead.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum Gradient {
+    /// Full-saturation HSL, the board the game has always had.
+    #[default]
+    Hsl,
+    /// Perceptually even steps: equal lightness per row, chroma reduced only where sRGB cannot show it.
+    Oklch,
+}
+
+impl Gradient {
+    fn as_str(self) -> &'static str {
+        match self { Gradient::Hsl => "hsl", Gradient::Oklch => "oklch" }
+    }
+}
+
+const MIN_SIDE: u32 = 2;
+const MAX_COLS: u32 = 60;
+const MAX_ROWS: u32 = 40;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Board {
+    pub gradient: Gradient,
+    pub cols: u32,
+    pub rows: u32,
+}
+
+impl Board {
+    pub(crate) const CLASSIC: Board = Board { gradient: Gradient::Hsl, cols: 30, rows: 18 };
+
+    /// Stable name of the palette, e.g. `hsl-30x18`.
+    pub(crate) fn id(&self) -> String {
+        format!("{}-{}x{}", self.gradient.as_str(), self.cols, self.rows)
+    }
+
+    pub(crate) fn from_id(id: &str) -> Option<Board> {
+        let (gradient, size) = id.split_once('-')?;
+        let gradient = [Gradient::Hsl, Gradient::Oklch].into_iter().find(|g| g.as_str() == gradient)?;
+        let (cols, rows) = size.split_once('x')?;
+        let (cols, rows) = (cols.parse().ok()?, rows.parse().ok()?);
+        if !(MIN_SIDE..=MAX_COLS).contains(&cols) || !(MIN_SIDE..=MAX_ROWS).contains(&rows) { return None; }
+        Some(Board { gradient, cols, rows })
+    }
+
+    pub(crate) fn size(&self) -> usize {
+        (self.cols * self.rows) as usize
+    }
+
+    /// Hue runs along the columns; rows go from light at the top to dark at the bottom.
+    pub(crate) fn rgb(&self, idx: usize) -> Rgb {
+        let (r, c) = (idx / self.cols as usize, idx % self.cols as usize);
+        let t = r as f32 / (self.rows - 1) as f32;
+        match self.gradient {
+            Gradient::Hsl => {
+                let hue = (c as f32 / self.cols as f32 * 360.0).round();
+                let light = (72.0 + (22.0 - 72.0) * t).round();
+                hsl_to_rgb(hue, 1.0, light / 100.0)
+            }
+            Gradient::Oklch => {
+                let hue = c as f32 / self.cols as f32 * 360.0 + 30.0;
+                let light = 0.88 + (0.38 - 0.88) * t;
+                let mut chroma = 0.2;
+                loop {
+                    if let Some(rgb) = oklch_to_rgb(light, chroma, hue) { break rgb; }
+                    chroma -= 0.005;
+                    if chroma <= 0.0 { break oklch_to_rgb(light, 0.0, hue).unwrap_or([128, 128, 128]); }
+                }
+            }
+        }
+    }
+
+    pub(crate) fn lab(&self, idx: usize) -> Lab {
+        rgb_to_lab(self.rgb(idx))
+    }
+
+    /// `#rrggbb` for every cell, row by row.
+    pub(crate) fn palette(&self) -> Vec<String> {
+        (0..self.size())
+            .map(|i| {
+                let [r, g, b] = self.rgb(i);
+                format!("#{:02x}{:02x}{:02x}", r, g, b)
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ids_round_trip() {
+        for board in [Board::CLASSIC, Board { gradient: Gradient::Oklch, ..Board::CLASSIC }, Board { gradient: Gradient::Hsl, cols: 12, rows: 8 }] {
+            assert_eq!(Board::from_id(&board.id()), Some(board));
+        }
+        assert_eq!(Board::from_id("hsl-1000x1000"), None);
+        assert_eq!(Board::from_id("rgb-30x18"), None);
+    }
+
+    #[test]
+    fn classic_board_matches_the_frontend_gradient() {
+        // Top-left is hsl(0 100% 72%), bottom-left hsl(0 100% 22%).
+        assert_eq!(Board::CLASSIC.rgb(0), [255, 112, 112]);
+        assert_eq!(Board::CLASSIC.rgb(17 * 30), [112, 0, 0]);
+    }
+
+    #[test]
+    fn oklch_rows_are_evenly_light() {
+        let board = Board { gradient: Gradient::Oklch, ..Board::CLASSIC };
+        for row in 0..board.rows as usize {
+            let ls: Vec<f32> = (0..board.cols as usize).map(|c| board.lab(row * board.cols as usize + c).l).collect();
+            let (min, max) = ls.iter().fold((f32::MAX, f32::MIN), |(lo, hi), l| (lo.min(*l), hi.max(*l)));
+            assert!(max - min < 6.0, "row {} lightness spread {}", row, max - min);
+        }
+    }
+}
@@ -0,0 +1,245 @@
+//! Server-side players. A bot occupies a seat like a connection without a socket;
+//! the hub polls each [`Bot`] and feeds its commands through the same path as human input.
+
+use std::time::{Duration, Instant};
+
+use rand::{seq::SliceRandom, Rng};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::{
+    color::{delta_e, Lab},
+    game::Command,
+    lexicon::{self, Lang},
+    Phase, RoomState,
+};
+
+pub(crate) const MAX_BOTS_PER_ROOM: usize = 6;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum BotDifficulty { Easy, #[default] Medium, Hard }
+
+impl BotDifficulty {
+    /// How many of the best-ranked choices the bot picks among.
+    fn spread(self) -> usize {
+        match self { BotDifficulty::Easy => 25, BotDifficulty::Medium => 5, BotDifficulty::Hard => 1 }
+    }
+
+    fn think_time(self) -> Duration {
+        let ms = match self { BotDifficulty::Easy => 2500, BotDifficulty::Medium => 1800, BotDifficulty::Hard => 1200 };
+        Duration::from_millis(rand::thread_rng().gen_range(ms / 2..=ms))
+    }
+}
+
+#[derive(Debug)]
+pub(crate) struct Bot {
+    pub difficulty: BotDifficulty,
+    pub lang: Lang,
+    /// The situation the bot is about to act on and when it will, so it "thinks" once per turn.
+    pending: Option<(Turn, Instant)>,
+}
+
+/// Round, phase and whether a target is chosen: changes whenever the bot may have something new to do.
+type Turn = (u32, Phase, bool);
+
+impl Bot {
+    pub(crate) fn new(difficulty: BotDifficulty, lang: Lang) -> Self {
+        Self { difficulty, lang, pending: None }
+    }
+
+    /// The command to send now, if the bot has something to do and has finished thinking about it.
+    pub(crate) fn poll(&mut self, room: &RoomState, me: Uuid, now: Instant) -> Option<Command> {
+        let turn = (room.round, room.phase, room.target.is_some());
+        if !has_move(room, me) {
+            self.pending = None;
+            return None;
+        }
+        match self.pending {
+            Some((t, at)) if t == turn => {
+                if now < at { return None; }
+                self.pending = None;
+                decide(room, me, self.difficulty, self.lang)
+            }
+            _ => {
+                self.pending = Some((turn, now + self.difficulty.think_time()));
+                None
+            }
+        }
+    }
+}
+
+pub(crate) fn name(lang: Lang, difficulty: BotDifficulty) -> String {
+    let names: &[&str] = match lang {
+        Lang::En => &["Robo Ruby", "Robo Jade", "Robo Indigo", "Robo Amber", "Robo Coral", "Robo Sage"],
+        Lang::Ru => &["Робо Рубин", "Робо Нефрит", "Робо Индиго", "Робо Янтарь", "Робо Коралл", "Робо Шалфей"],
+    };
+    let level = match (lang, difficulty) {
+        (Lang::En, BotDifficulty::Easy) => "easy",
+        (Lang::En, BotDifficulty::Medium) => "medium",
+        (Lang::En, BotDifficulty::Hard) => "hard",
+        (Lang::Ru, BotDifficulty::Easy) => "лёгкий",
+        (Lang::Ru, BotDifficulty::Medium) => "средний",
+        (Lang::Ru, BotDifficulty::Hard) => "сложный",
+    };
+    format!("{} ({})", names.choose(&mut rand::thread_rng()).unwrap(), level)
+}
+
+fn is_cue_giver(room: &RoomState, me: Uuid) -> bool {
+    room.players.get(room.cue_giver_idx).is_some_and(|p| p.id == me)
+}
+
+fn has_move(room: &RoomState, me: Uuid) -> bool {
+    let giver = is_cue_giver(room, me);
+    match room.phase {
+        Phase::Cue1 | Phase::Cue2 => giver,
+        Phase::Guess1 => !giver && !room.guessed_once.contains(&me),
+        Phase::Guess2 => !giver && !room.guessed_twice.contains(&me),
+        Phase::Lobby | Phase::Reveal | Phase::GameOver => false,
+    }
+}
+
+/// What the seat `me` should do in `room`, at the given skill.
+pub(crate) fn decide(room: &RoomState, me: Uuid, difficulty: BotDifficulty, lang: Lang) -> Option<Command> {
+    if !has_move(room, me) { return None; }
+    let mut rng = rand::thread_rng();
+    match room.phase {
+        Phase::Cue1 => match (&room.select_options, room.target) {
+            (Some(options), None) => Some(Command::ChooseTarget { index: choose_target(room, options, difficulty) }),
+            (_, target) => {
+                let target = target.unwrap_or_else(|| rng.gen_range(0..board_size(room)));
+                let allowed = |cue: &str| room.cue_rules.check_cue1(cue).is_ok();
+                Some(cue1(cell_lab(room, target), allowed, difficulty, lang).map_or(Command::PassCue, |cue| Command::LockCue1 { cue }))
+            }
+        },
+        Phase::Cue2 => {
+            let target = room.target.unwrap_or_else(|| rng.gen_range(0..board_size(room)));
+            let first = room.cue1.as_deref().unwrap_or_default();
+            let allowed = |cue: &str| room.cue_rules.check_cue2(cue).is_ok();
+            Some(cue2(cell_lab(room, target), first, allowed, difficulty, lang).map_or(Command::PassCue, |cue2| Command::LockCue2 { cue2 }))
+        }
+        Phase::Guess1 | Phase::Guess2 => {
+            let cues: Vec<&str> = [room.cue1.as_deref(), room.cue2.as_deref()].into_iter().flatten().collect();
+            Some(Command::Guess { cell: guess(room, &cues, difficulty) })
+        }
+        Phase::Lobby | Phase::Reveal | Phase::GameOver => None,
+    }
+}
+
+fn board_size(room: &RoomState) -> usize {
+    room.board.size()
+}
+
+fn cell_lab(room: &RoomState, idx: usize) -> Lab {
+    room.board.lab(idx)
+}
+
+/// Picks randomly among the `difficulty.spread()` best items by ascending cost.
+fn pick_ranked<T: Copy>(mut ranked: Vec<(f32, T)>, difficulty: BotDifficulty) -> Option<T> {
+    ranked.sort_by(|a, b| a.0.total_cmp(&b.0));
+    ranked.truncate(difficulty.spread());
+    ranked.choose(&mut rand::thread_rng()).map(|(_, t)| *t)
+}
+
+/// Words a cue giver may use: things rather than plain colour names.
+fn describing_words() -> impl Iterator<Item = &'static lexicon::ColorWord> {
+    lexicon::WORDS.iter().filter(|w| !w.basic)
+}
+
+fn nearest_word_distance(lab: Lab) -> f32 {
+    describing_words().map(|w| delta_e(w.lab(), lab)).fold(f32::MAX, f32::min)
+}
+
+/// Skilled bots take the option that is easiest to describe; weak ones take any.
+fn choose_target(room: &RoomState, options: &[usize], difficulty: BotDifficulty) -> usize {
+    let ranked = options.iter().map(|&i| (nearest_word_distance(cell_lab(room, i)), i)).collect();
+    let difficulty = if difficulty == BotDifficulty::Hard { difficulty } else { BotDifficulty::Easy };
+    pick_ranked(ranked, difficulty).unwrap_or(options[0])
+}
+
+/// The best word the rules allow: a thing if any is allowed, otherwise a plain colour name.
+/// `None` when the rules allow no word at all; the bot then passes.
+fn cue1(target: Lab, allowed: impl Fn(&str) -> bool, difficulty: BotDifficulty, lang: Lang) -> Option<String> {
+    [true, false].into_iter().find_map(|things_only| {
+        let ranked = lexicon::WORDS.iter()
+            .filter(|w| (!things_only || !w.basic) && allowed(w.word(lang)))
+            .map(|w| (delta_e(w.lab(), target), w))
+            .collect();
+        pick_ranked(ranked, difficulty).map(|w| w.word(lang).to_string())
+    })
+}
+
+/// Up to two words that, read together with the first cue, land nearest the target;
+/// like [`cue1`] it falls back to plain colour names and gives up when the rules allow nothing.
+fn cue2(target: Lab, first: &str, allowed: impl Fn(&str) -> bool, difficulty: BotDifficulty, lang: Lang) -> Option<String> {
+    let (lighter, darker) = match lang { Lang::En => ("light", "dark"), Lang::Ru => ("светлый", "тёмный") };
+    [true, false].into_iter().find_map(|things_only| {
+        let mut candidates: Vec<String> = Vec::new();
+        for w in lexicon::WORDS.iter().filter(|w| !things_only || !w.basic) {
+            let word = w.word(lang);
+            candidates.push(word.to_string());
+            candidates.push(format!("{} {}", lighter, word));
+            candidates.push(format!("{} {}", darker, word));
+        }
+        let ranked = candidates.iter().enumerate()
+            .filter(|(_, c)| lexicon::normalize(c) != lexicon::normalize(first) && allowed(c))
+            .filter_map(|(i, c)| lexicon::interpret(&[first, c]).map(|lab| (delta_e(lab, target), i)))
+            .collect();
+        pick_ranked(ranked, difficulty).map(|i| candidates[i].clone())
+    })
+}
+
+/// The cell closest to what the cues describe; a random cell when no word is understood.
+fn guess(room: &RoomState, cues: &[&str], difficulty: BotDifficulty) -> usize {
+    let Some(meant) = lexicon::interpret(cues) else {
+        return rand::thread_rng().gen_range(0..board_size(room));
+    };
+    let ranked = (0..board_size(room)).map(|i| (delta_e(cell_lab(room, i), meant), i)).collect();
+    pick_ranked(ranked, difficulty).unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn grid_distance(room: &RoomState, a: usize, b: usize) -> usize {
+        let cols = room.board.cols as usize;
+        (a / cols).abs_diff(b / cols).max((a % cols).abs_diff(b % cols))
+    }
+
+    #[test]
+    fn hard_bots_understand_each_other() {
+        let room = crate::default_room();
+        let mut near = 0;
+        let targets: Vec<usize> = (0..board_size(&room)).step_by(37).collect();
+        for &target in &targets {
+            let lab = cell_lab(&room, target);
+            let first = cue1(lab, |c| room.cue_rules.check_cue1(c).is_ok(), BotDifficulty::Hard, Lang::En).unwrap();
+            let second = cue2(lab, &first, |c| room.cue_rules.check_cue2(c).is_ok(), BotDifficulty::Hard, Lang::En).unwrap();
+            let cell = guess(&room, &[&first, &second], BotDifficulty::Hard);
+            if grid_distance(&room, cell, target) <= 3 { near += 1; }
+        }
+        assert!(near * 2 >= targets.len(), "only {} of {} guesses landed near the target", near, targets.len());
+    }
+
+    #[test]
+    fn cue_givers_avoid_plain_colour_names() {
+        let room = crate::default_room();
+        for target in [0, 100, 250, 400, 539] {
+            for lang in [Lang::En, Lang::Ru] {
+                let cue = cue1(cell_lab(&room, target), |c| room.cue_rules.check_cue1(c).is_ok(), BotDifficulty::Medium, lang).unwrap();
+                assert!(lexicon::lookup(&cue).is_some_and(|w| !w.basic), "{}", cue);
+                assert_eq!(room.cue_rules.check_cue1(&cue), Ok(()));
+            }
+        }
+    }
+
+    #[test]
+    fn bots_pass_when_the_rules_allow_no_word() {
+        let lab = cell_lab(&crate::default_room(), 100);
+        let plain = |c: &str| lexicon::lookup(c).is_some_and(|w| w.basic);
+        assert!(cue1(lab, plain, BotDifficulty::Hard, Lang::En).is_some_and(|c| plain(&c)), "falls back to a colour name");
+        assert_eq!(cue1(lab, |_| false, BotDifficulty::Hard, Lang::En), None);
+        assert_eq!(cue2(lab, "sky", |_| false, BotDifficulty::Hard, Lang::Ru), None);
+    }
+}
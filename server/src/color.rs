@@ -0,0 +1,78 @@
+//! Colour conversions used by the board, bots and scoring.
+
+pub(crate) type Rgb = [u8; 3];
+
+/// CIELAB (D65), where Euclidean distance roughly follows perceived difference.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) struct Lab { pub l: f32, pub a: f32, pub b: f32 }
+
+impl Lab {
+    pub(crate) fn mean(colors: &[Lab]) -> Option<Lab> {
+        if colors.is_empty() { return None; }
+        let n = colors.len() as f32;
+        Some(Lab {
+            l: colors.iter().map(|c| c.l).sum::<f32>() / n,
+            a: colors.iter().map(|c| c.a).sum::<f32>() / n,
+            b: colors.iter().map(|c| c.b).sum::<f32>() / n,
+        })
+    }
+}
+
+/// `h` in degrees, `s` and `l` in 0..=1.
+pub(crate) fn hsl_to_rgb(h: f32, s: f32, l: f32) -> Rgb {
+    let c = (1.0 - (2.0 * l - 1.0).abs()) * s;
+    let hp = h.rem_euclid(360.0) / 60.0;
+    let x = c * (1.0 - (hp % 2.0 - 1.0).abs());
+    let (r, g, b) = match hp as u32 {
+        0 => (c, x, 0.0),
+        1 => (x, c, 0.0),
+        2 => (0.0, c, x),
+        3 => (0.0, x, c),
+        4 => (x, 0.0, c),
+        _ => (c, 0.0, x),
+    };
+    let m = l - c / 2.0;
+    let to_u8 = |v: f32| ((v + m) * 255.0).round().clamp(0.0, 255.0) as u8;
+    [to_u8(r), to_u8(g), to_u8(b)]
+}
+
+pub(crate) fn rgb_to_lab(rgb: Rgb) -> Lab {
+    let lin = |v: u8| {
+        let v = v as f32 / 255.0;
+        if v <= 0.04045 { v / 12.92 } else { ((v + 0.055) / 1.055).powf(2.4) }
+    };
+    let (r, g, b) = (lin(rgb[0]), lin(rgb[1]), lin(rgb[2]));
+    let x = (0.4124 * r + 0.3576 * g + 0.1805 * b) / 0.95047;
+    let y = 0.2126 * r + 0.7152 * g + 0.0722 * b;
+    let z = (0.0193 * r + 0.1192 * g + 0.9505 * b) / 1.08883;
+    let f = |t: f32| if t > 0.008856 { t.cbrt() } else { 7.787 * t + 16.0 / 116.0 };
+    let (fx, fy, fz) = (f(x), f(y), f(z));
+    Lab { l: 116.0 * fy - 16.0, a: 500.0 * (fx - fy), b: 200.0 * (fy - fz) }
+}
+
+/// CIE76 ΔE.
+pub(crate) fn delta_e(a: Lab, b: Lab) -> f32 {
+    ((a.l - b.l).powi(2) + (a.a - b.a).powi(2) + (a.b - b.b).powi(2)).sqrt()
+}
+
+/// OKLCH (`l` 0..=1, chroma, hue in degrees) to sRGB; `None` when the colour is outside the sRGB gamut.
+pub(crate) fn oklch_to_rgb(l: f32, c: f32, h: f32) -> Option<Rgb> {
+    let (a, b) = (c * h.to_radians().cos(), c * h.to_radians().sin());
+    let l_ = l + 0.396_337_78 * a + 0.215_803_76 * b;
+    let m_ = l - 0.105_561_346 * a - 0.063_854_17 * b;
+    let s_ = l - 0.089_484_18 * a - 1.291_485_5 * b;
+    let (l3, m3, s3) = (l_.powi(3), m_.powi(3), s_.powi(3));
+    let lin = [
+        4.076_741_7 * l3 - 3.307_711_6 * m3 + 0.230_969_94 * s3,
+        -1.268_438 * l3 + 2.609_757_4 * m3 - 0.341_319_38 * s3,
+        -0.004_196_086_3 * l3 - 0.703_418_6 * m3 + 1.707_614_7 * s3,
+    ];
+    const EPS: f32 = 1e-4;
+    if lin.iter().any(|v| *v < -EPS || *v > 1.0 + EPS) { return None; }
+    let encode = |v: f32| {
+        let v = v.clamp(0.0, 1.0);
+        let v = if v <= 0.003_130_8 { 12.92 * v } else { 1.055 * v.powf(1.0 / 2.4) - 0.055 };
+        (v * 255.0).round() as u8
+    };
+    Some([encode(lin[0]), encode(lin[1]), encode(lin[2])])
+}
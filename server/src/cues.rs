@@ -0,0 +1,132 @@
+//! What a cue may say. Each room has its own [`CueRules`]; the defaults are the board game's:
+//! one word first, at most two the second time, no colour names and nothing pointing at the grid.
+
+use serde::{Deserialize, Serialize};
+
+use crate::lexicon::{self, normalize};
+
+/// Per-room cue rules; the host may change them (see `game::Command::SetCueRules`).
+/// Fields missing from a client's message take their default.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CueRules {
+    /// Most words in the first cue; 0 means no limit.
+    pub cue1_words: u8,
+    /// Most words in the second cue; 0 means no limit.
+    pub cue2_words: u8,
+    pub forbid_color_names: bool,
+    pub forbid_positions: bool,
+}
+
+impl Default for CueRules {
+    fn default() -> Self {
+        Self { cue1_words: 1, cue2_words: 2, forbid_color_names: true, forbid_positions: true }
+    }
+}
+
+/// Longest cue in characters, whatever the rules: it is shown to every player and checked word by word.
+const MAX_CUE_CHARS: usize = 64;
+
+/// Colour names besides the lexicon's basic ones, and words about colour itself.
+const COLOR_NAMES: &[&str] = &[
+    "grey", "beige", "crimson", "scarlet", "magenta", "color", "colour", "hue",
+    "алый", "бордовый", "бежевый", "салатовый", "лиловый", "цвет", "цветной", "оттенок",
+];
+
+/// Words that locate a cell instead of describing its colour.
+const POSITION_WORDS: &[&str] = &[
+    "row", "column", "col", "top", "bottom", "left", "right", "corner", "middle", "center", "centre", "edge", "cell", "square",
+    "ряд", "строка", "столбец", "колонка", "верх", "низ", "левый", "правый", "слева", "справа", "сверху", "снизу", "угол", "центр", "середина", "клетка", "край",
+];
+
+impl CueRules {
+    pub(crate) fn check_cue1(&self, cue: &str) -> Result<(), &'static str> {
+        self.check(cue, self.cue1_words)
+    }
+
+    pub(crate) fn check_cue2(&self, cue: &str) -> Result<(), &'static str> {
+        self.check(cue, self.cue2_words)
+    }
+
+    fn check(&self, cue: &str, max_words: u8) -> Result<(), &'static str> {
+        // Hyphenated compounds ("тёмно-синий") count as one word but each part is checked.
+        let count = cue.split_whitespace().count();
+        if count == 0 { return Err("cue is empty"); }
+        if cue.trim().chars().count() > MAX_CUE_CHARS { return Err("cue is too long"); }
+        if max_words > 0 && count > max_words as usize { return Err("cue has too many words"); }
+        if self.forbid_positions && cue.chars().any(|c| c.is_ascii_digit()) {
+            return Err("cues may not point at board positions");
+        }
+        for word in lexicon::words(cue) {
+            if self.forbid_color_names && is_color_name(&word) { return Err("cues may not name a colour"); }
+            if self.forbid_positions && !is_thing(&word) && POSITION_WORDS.iter().any(|p| same_stem(&word, p)) {
+                return Err("cues may not point at board positions");
+            }
+        }
+        Ok(())
+    }
+}
+
+fn is_color_name(word: &str) -> bool {
+    lexicon::WORDS.iter().filter(|w| w.basic).any(|w| same_stem(word, w.en) || same_stem(word, w.ru))
+        || COLOR_NAMES.iter().any(|n| same_stem(word, n))
+}
+
+/// Exactly a thing from the lexicon; those describe a colour even when they look like a position word ("уголь", coal).
+fn is_thing(word: &str) -> bool {
+    lexicon::WORDS.iter().any(|w| !w.basic && (normalize(w.en) == word || normalize(w.ru) == word))
+}
+
+/// The same word or an inflected form of it ("красного", "верхний"): they differ only in a short ending.
+/// Stricter than `lexicon::similar`, which would forbid innocent neighbours such as "pine" for "pink" or "cornflower" for "corner".
+fn same_stem(typed: &str, known: &str) -> bool {
+    let known = normalize(known);
+    if typed == known { return true; }
+    let prefix = typed.chars().zip(known.chars()).take_while(|(a, b)| a == b).count();
+    let (typed_len, known_len) = (typed.chars().count(), known.chars().count());
+    prefix >= 4 && prefix + 2 >= known_len && typed_len <= known_len + 3
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn default_rules_follow_the_board_game() {
+        let rules = CueRules::default();
+        assert_eq!(rules.check_cue1("sky"), Ok(()));
+        assert_eq!(rules.check_cue1("clear sky"), Err("cue has too many words"));
+        assert_eq!(rules.check_cue2("тёмно-небо"), Ok(()));
+        assert_eq!(rules.check_cue2("красного"), Err("cues may not name a colour"));
+        assert_eq!(rules.check_cue2("Grey sky"), Err("cues may not name a colour"));
+        assert_eq!(rules.check_cue1("B12"), Err("cues may not point at board positions"));
+        assert_eq!(rules.check_cue2("верхний угол"), Err("cues may not point at board positions"));
+        assert_eq!(rules.check_cue1("  "), Err("cue is empty"));
+    }
+
+    #[test]
+    fn relaxed_rules_allow_more() {
+        let rules = CueRules { cue1_words: 0, forbid_color_names: false, ..CueRules::default() };
+        assert_eq!(rules.check_cue1("red like a fire truck"), Ok(()));
+        assert_eq!(rules.check_cue1("top left"), Err("cues may not point at board positions"));
+    }
+
+    #[test]
+    fn long_cues_are_refused_under_any_rules() {
+        let rules = CueRules { cue1_words: 0, cue2_words: 0, forbid_color_names: false, forbid_positions: false };
+        let longest = "я".repeat(MAX_CUE_CHARS);
+        assert_eq!(rules.check_cue1(&longest), Ok(()), "the limit counts characters, not bytes");
+        assert_eq!(rules.check_cue2(&format!("  {}  ", longest)), Ok(()));
+        assert_eq!(rules.check_cue1(&format!("{}я", longest)), Err("cue is too long"));
+        assert_eq!(rules.check_cue2(&"sky ".repeat(20)), Err("cue is too long"));
+    }
+
+    #[test]
+    fn every_lexicon_thing_is_a_valid_cue() {
+        let rules = CueRules::default();
+        for w in lexicon::WORDS.iter().filter(|w| !w.basic) {
+            assert_eq!(rules.check_cue1(w.en), Ok(()), "{}", w.en);
+            assert_eq!(rules.check_cue1(w.ru), Ok(()), "{}", w.ru);
+        }
+    }
+}
@@ -0,0 +1,692 @@
+//! Hues and Cues rules as a pure reducer. The hub resolves who sent a message,
+//! calls [`reduce`] and carries out the returned effects; nothing here locks or sends.
+
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::{
+    cues::CueRules,
+    scoring::{GuessUsed, PlayerRound, RoundResult, Scoring},
+    Phase, Player, RoomState,
+};
+
+/// A seated player's action. `Join` carries the seat being created.
+#[derive(Debug, Clone)]
+pub(crate) enum Command {
+    Join { name: String, user_id: Option<Uuid>, avatar: Option<String>, bot: bool },
+    Leave,
+    /// `force` lets the host start without waiting for everyone to be ready.
+    StartGame { force: bool },
+    SetReady { ready: bool },
+    SetPlayerLimits { limits: PlayerLimits },
+    ChooseTarget { index: usize },
+    LockCue1 { cue: String },
+    LockCue2 { cue2: String },
+    /// The cue giver has nothing to say: the round is revealed with the guesses made so far.
+    PassCue,
+    Guess { cell: usize },
+    NextRound,
+    SetCueRules { rules: CueRules },
+    SetScoring { scoring: Scoring },
+    SetGameLength { length: GameLength },
+    /// A new game with the same players and settings, once the last one is over.
+    Rematch,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum Effect {
+    /// The room changed; everyone seated gets the new state.
+    Broadcast,
+    /// The command was refused; only the sender is told why.
+    Reject(&'static str),
+}
+
+/// Number of target cells the cue giver picks from.
+const TARGET_OPTIONS: usize = 4;
+
+/// When a game ends: at the reveal that completes `rotations` turns of cue giving for everyone seated at the start,
+/// or at the first reveal where someone has `score_target` points, whichever comes first.
+/// A zero turns that condition off; with both off the game goes on until players leave.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct GameLength {
+    pub rotations: u32,
+    pub score_target: i32,
+}
+
+impl Default for GameLength {
+    /// Everyone gives cues twice, as in the tabletop game for up to six players, unless someone reaches 15 first.
+    fn default() -> Self {
+        Self { rotations: 2, score_target: 15 }
+    }
+}
+
+/// How many seats a room has and how many must be taken to start. Each game kind has its own
+/// bounds (`GameKind::player_limits`); a host may narrow them for a room.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PlayerLimits {
+    pub min: u32,
+    pub max: u32,
+}
+
+const NOT_ENOUGH_PLAYERS: &str = "not enough players";
+const NOT_EVERYONE_READY: &str = "not everyone is ready";
+
+/// What keeps the room from starting a game now; empty when it can start.
+pub(crate) fn start_blockers(state: &RoomState) -> Vec<&'static str> {
+    let mut blockers = Vec::new();
+    if state.players.len() < state.limits.min as usize { blockers.push(NOT_ENOUGH_PLAYERS); }
+    if state.players.iter().any(|p| !p.ready) { blockers.push(NOT_EVERYONE_READY); }
+    blockers
+}
+
+/// A player's final place. Ties on score go to whoever made more 3-point guesses;
+/// players still level share the place.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Standing {
+    pub player: Uuid,
+    pub name: String,
+    pub place: u32,
+    pub score: i32,
+    pub bullseyes: u32,
+}
+
+/// Applies `cmd` from `player`. Identical inputs give identical outputs: randomness comes from `state.seed`.
+pub(crate) fn reduce(mut state: RoomState, player: Uuid, cmd: Command) -> (RoomState, Vec<Effect>) {
+    let seat = state.players.iter().position(|p| p.id == player);
+    let is_cue_giver = seat.is_some() && seat == Some(state.cue_giver_idx) && state.phase != Phase::Lobby;
+    // The longest-seated player hosts the room.
+    let is_host = seat == Some(0);
+    let reject = |state, reason| (state, vec![Effect::Reject(reason)]);
+    match cmd {
+        Command::Join { name, user_id, avatar, bot } => {
+            if seat.is_some() { return (state, vec![]); }
+            if state.players.len() >= state.limits.max as usize { return reject(state, "room is full"); }
+            // Bots are always ready.
+            state.players.push(Player { id: player, name, score: 0, bullseyes: 0, ready: bot, user_id, avatar, bot });
+        }
+        _ if seat.is_none() => return reject(state, "not in this room"),
+        Command::Leave => {
+            let Some(idx) = seat else { return (state, vec![]) };
+            state.players.remove(idx);
+            state.guessed_once.remove(&player);
+            state.guessed_twice.remove(&player);
+            state.guess1_cells.remove(&player);
+            state.guess2_cells.remove(&player);
+            if state.players.is_empty() {
+                reset_to_lobby(&mut state);
+            } else if idx < state.cue_giver_idx {
+                state.cue_giver_idx -= 1;
+            } else if idx == state.cue_giver_idx {
+                let n = state.players.len();
+                if matches!(state.phase, Phase::Reveal | Phase::GameOver) {
+                    // Step back so that the next round still goes to the player after the one who left.
+                    state.cue_giver_idx = (idx + n - 1) % n;
+                } else {
+                    // The cue giver's target left with them: replay the round with whoever now holds the seat.
+                    state.cue_giver_idx = idx % n;
+                    if state.phase != Phase::Lobby { start_round(&mut state); }
+                }
+            }
+            advance_if_all_guessed(&mut state);
+        }
+        Command::StartGame { force } => {
+            // Never mid-game: a start wipes the scores.
+            if !matches!(state.phase, Phase::Lobby | Phase::GameOver) { return reject(state, "wrong phase"); }
+            let blockers = start_blockers(&state);
+            if blockers.contains(&NOT_ENOUGH_PLAYERS) { return reject(state, NOT_ENOUGH_PLAYERS); }
+            if force && !is_host { return reject(state, "only the host can force a start"); }
+            if !force && !blockers.is_empty() { return reject(state, blockers[0]); }
+            start_game(&mut state);
+        }
+        Command::Rematch => {
+            if state.phase != Phase::GameOver { return reject(state, "wrong phase"); }
+            // The same table plays again, so nobody has to ready up; it still has to be big enough.
+            if state.players.len() < state.limits.min as usize { return reject(state, NOT_ENOUGH_PLAYERS); }
+            start_game(&mut state);
+        }
+        Command::SetReady { ready } => {
+            if !matches!(state.phase, Phase::Lobby | Phase::GameOver) { return reject(state, "wrong phase"); }
+            if let Some(idx) = seat { state.players[idx].ready = ready; }
+        }
+        Command::SetPlayerLimits { limits } => {
+            if !is_host { return reject(state, "only the host can change the rules"); }
+            if !matches!(state.phase, Phase::Lobby | Phase::GameOver) { return reject(state, "wrong phase"); }
+            let bounds = state.game.player_limits();
+            if limits.min < bounds.min || limits.max > bounds.max || limits.min > limits.max {
+                return reject(state, "player limits out of range");
+            }
+            if (state.players.len() as u32) > limits.max { return reject(state, "more players seated than that"); }
+            state.limits = limits;
+        }
+        Command::ChooseTarget { index } => {
+            if state.phase != Phase::Cue1 { return reject(state, "wrong phase"); }
+            if !is_cue_giver { return reject(state, "only the cue giver can choose the target"); }
+            if !state.select_options.as_ref().is_some_and(|o| o.contains(&index)) { return reject(state, "not one of the offered cells"); }
+            state.target = Some(index);
+            state.select_options = None;
+        }
+        Command::LockCue1 { cue } => {
+            if state.phase != Phase::Cue1 { return reject(state, "wrong phase"); }
+            if !is_cue_giver { return reject(state, "only the cue giver can give a cue"); }
+            if let Err(reason) = state.cue_rules.check_cue1(&cue) { return reject(state, reason); }
+            state.cue1 = Some(cue);
+            state.phase = Phase::Guess1;
+            state.guessed_once.clear();
+            state.guess1_cells.clear();
+            advance_if_all_guessed(&mut state);
+        }
+        Command::LockCue2 { cue2 } => {
+            if state.phase != Phase::Cue2 { return reject(state, "wrong phase"); }
+            if !is_cue_giver { return reject(state, "only the cue giver can give a cue"); }
+            if let Err(reason) = state.cue_rules.check_cue2(&cue2) { return reject(state, reason); }
+            state.cue2 = Some(cue2);
+            state.phase = Phase::Guess2;
+            state.guessed_twice.clear();
+            state.guess2_cells.clear();
+            advance_if_all_guessed(&mut state);
+        }
+        Command::PassCue => {
+            if !matches!(state.phase, Phase::Cue1 | Phase::Cue2) { return reject(state, "wrong phase"); }
+            if !is_cue_giver { return reject(state, "only the cue giver can pass"); }
+            reveal(&mut state);
+        }
+        Command::Guess { cell } => {
+            if !matches!(state.phase, Phase::Guess1 | Phase::Guess2) { return reject(state, "wrong phase"); }
+            if is_cue_giver { return reject(state, "the cue giver does not guess"); }
+            if cell >= board_size(&state) { return reject(state, "cell out of range"); }
+            if state.phase == Phase::Guess1 {
+                state.guessed_once.insert(player);
+                state.guess1_cells.insert(player, cell);
+            } else {
+                state.guessed_twice.insert(player);
+                state.guess2_cells.insert(player, cell);
+            }
+            advance_if_all_guessed(&mut state);
+        }
+        Command::NextRound => {
+            if state.phase != Phase::Reveal { return reject(state, "wrong phase"); }
+            state.round += 1;
+            state.cue_giver_idx = (state.cue_giver_idx + 1) % state.players.len();
+            start_round(&mut state);
+        }
+        Command::SetCueRules { rules } => {
+            if !is_host { return reject(state, "only the host can change the rules"); }
+            // Not while a cue is being given or guessed: it was checked against the old rules.
+            if !matches!(state.phase, Phase::Lobby | Phase::Reveal | Phase::GameOver) { return reject(state, "wrong phase"); }
+            state.cue_rules = rules;
+        }
+        Command::SetScoring { scoring } => {
+            if !is_host { return reject(state, "only the host can change the rules"); }
+            // Not mid-round: guesses were placed under the old scoring.
+            if !matches!(state.phase, Phase::Lobby | Phase::Reveal) { return reject(state, "wrong phase"); }
+            state.scoring = scoring;
+        }
+        Command::SetGameLength { length } => {
+            if !is_host { return reject(state, "only the host can change the rules"); }
+            if !matches!(state.phase, Phase::Lobby | Phase::GameOver) { return reject(state, "wrong phase"); }
+            state.game_length = length;
+        }
+    }
+    (state, vec![Effect::Broadcast])
+}
+
+fn board_size(state: &RoomState) -> usize {
+    state.board.size()
+}
+
+/// Draws from the room's seed and moves it on, so the next draw differs but stays reproducible.
+fn next_rng(state: &mut RoomState) -> StdRng {
+    let rng = StdRng::seed_from_u64(state.seed);
+    state.seed = state.seed.wrapping_add(0x9E37_79B9_7F4A_7C15);
+    rng
+}
+
+fn start_game(state: &mut RoomState) {
+    for p in state.players.iter_mut() {
+        p.score = 0;
+        p.bullseyes = 0;
+        p.ready = p.bot;
+    }
+    state.standings = None;
+    state.seats_at_start = state.players.len();
+    state.round = 1;
+    state.cue_giver_idx = 0;
+    start_round(state);
+}
+
+fn start_round(state: &mut RoomState) {
+    let mut rng = next_rng(state);
+    state.phase = Phase::Cue1;
+    state.cue1 = None;
+    state.cue2 = None;
+    state.target = None;
+    state.round_result = None;
+    state.select_options = Some(rand_unique_indices(&mut rng, board_size(state), TARGET_OPTIONS));
+    state.guessed_once.clear();
+    state.guessed_twice.clear();
+    state.guess1_cells.clear();
+    state.guess2_cells.clear();
+}
+
+fn reset_to_lobby(state: &mut RoomState) {
+    state.phase = Phase::Lobby;
+    state.round = 0;
+    state.cue_giver_idx = 0;
+    state.seats_at_start = 0;
+    state.cue1 = None;
+    state.cue2 = None;
+    state.target = None;
+    state.round_result = None;
+    state.standings = None;
+    state.select_options = None;
+    state.guessed_once.clear();
+    state.guessed_twice.clear();
+    state.guess1_cells.clear();
+    state.guess2_cells.clear();
+}
+
+/// Moves a guessing phase on once every guesser has guessed, including when nobody is left to guess.
+fn advance_if_all_guessed(state: &mut RoomState) {
+    let guessed = match state.phase {
+        Phase::Guess1 => &state.guessed_once,
+        Phase::Guess2 => &state.guessed_twice,
+        _ => return,
+    };
+    let cue_giver = state.cue_giver_idx;
+    let all_done = state.players.iter().enumerate()
+        .filter(|(i, _)| *i != cue_giver)
+        .all(|(_, p)| guessed.contains(&p.id));
+    if !all_done { return; }
+    if state.phase == Phase::Guess1 {
+        state.phase = Phase::Cue2;
+    } else {
+        reveal(state);
+    }
+}
+
+/// Scores each guesser's latest guess against the target with the room's scoring, and the cue giver by how
+/// many guesses earned them a point; a cue giver who never chose a target gets a random cell.
+/// Ends the game instead of waiting for the next round once the room's [`GameLength`] is reached.
+fn reveal(state: &mut RoomState) {
+    let target = match state.target {
+        Some(t) => t,
+        None => next_rng(state).gen_range(0..board_size(state)),
+    };
+    state.target = Some(target);
+    state.select_options = None;
+    state.phase = Phase::Reveal;
+    let mut players = Vec::with_capacity(state.players.len());
+    let mut giver_points = 0;
+    for (i, pl) in state.players.iter_mut().enumerate() {
+        if i == state.cue_giver_idx { continue; }
+        let guess = match (state.guess2_cells.get(&pl.id), state.guess1_cells.get(&pl.id)) {
+            (Some(&cell), _) => Some((cell, GuessUsed::Second)),
+            (None, Some(&cell)) => Some((cell, GuessUsed::First)),
+            (None, None) => None,
+        };
+        let outcome = guess.map(|(cell, _)| state.scoring.score(&state.board, cell, target));
+        let points = outcome.map_or(0, |o| o.points);
+        giver_points += i32::from(outcome.is_some_and(|o| o.giver_point));
+        pl.score += points;
+        if points == 3 { pl.bullseyes += 1; }
+        players.push(PlayerRound {
+            player: pl.id,
+            guess: guess.map(|(cell, _)| cell),
+            guess_used: guess.map(|(_, used)| used),
+            distance: outcome.map(|o| o.distance),
+            points,
+            score: pl.score,
+        });
+    }
+    let cue_giver = &mut state.players[state.cue_giver_idx];
+    cue_giver.score += giver_points;
+    players.insert(state.cue_giver_idx, PlayerRound {
+        player: cue_giver.id, guess: None, guess_used: None, distance: None, points: giver_points, score: cue_giver.score,
+    });
+    state.round_result = Some(RoundResult {
+        round: state.round, scoring: state.scoring, target, cue_giver: cue_giver.id, cue_giver_points: giver_points, players,
+    });
+
+    let length = &state.game_length;
+    let rotations_done = length.rotations > 0 && state.round as usize >= length.rotations as usize * state.seats_at_start;
+    let target_reached = length.score_target > 0 && state.players.iter().any(|p| p.score >= length.score_target);
+    if rotations_done || target_reached {
+        state.phase = Phase::GameOver;
+        state.standings = Some(standings(&state.players));
+    }
+}
+
+fn standings(players: &[Player]) -> Vec<Standing> {
+    let mut ranked: Vec<&Player> = players.iter().collect();
+    ranked.sort_by_key(|p| std::cmp::Reverse((p.score, p.bullseyes)));
+    let mut standings: Vec<Standing> = Vec::with_capacity(ranked.len());
+    for (i, p) in ranked.into_iter().enumerate() {
+        let place = match standings.last() {
+            Some(prev) if (prev.score, prev.bullseyes) == (p.score, p.bullseyes) => prev.place,
+            _ => i as u32 + 1,
+        };
+        standings.push(Standing { player: p.id, name: p.name.clone(), place, score: p.score, bullseyes: p.bullseyes });
+    }
+    standings
+}
+
+fn rand_unique_indices(rng: &mut impl Rng, total: usize, count: usize) -> Vec<usize> {
+    rand::seq::index::sample(rng, total, count.min(total)).into_vec()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+    use std::collections::HashSet;
+
+    fn room(players: usize, seed: u64) -> RoomState {
+        let mut state = RoomState { seed, ..crate::default_room() };
+        for i in 0..players {
+            state = reduce(state, seat_id(i), Command::Join { name: format!("p{}", i), user_id: None, avatar: None, bot: false }).0;
+            state = reduce(state, seat_id(i), Command::SetReady { ready: true }).0;
+        }
+        state
+    }
+
+    fn seat_id(i: usize) -> Uuid {
+        Uuid::from_u128(i as u128 + 1)
+    }
+
+    /// Who sends a step: an existing seat (modulo the table), a newcomer, or someone never seated.
+    #[derive(Debug, Clone)]
+    enum Actor { Seat(usize), Newcomer, Stranger }
+
+    fn command() -> impl Strategy<Value = Command> {
+        prop_oneof![
+            1 => Just(Command::Join { name: "new".into(), user_id: None, avatar: None, bot: false }),
+            1 => Just(Command::Leave),
+            1 => any::<bool>().prop_map(|force| Command::StartGame { force }),
+            1 => any::<bool>().prop_map(|ready| Command::SetReady { ready }),
+            2 => (0..600usize).prop_map(|index| Command::ChooseTarget { index }),
+            2 => Just(Command::LockCue1 { cue: "sky".into() }),
+            2 => Just(Command::LockCue2 { cue2: "night sky".into() }),
+            1 => Just(Command::PassCue),
+            6 => (0..600usize).prop_map(|cell| Command::Guess { cell }),
+            2 => Just(Command::NextRound),
+            1 => Just(Command::Rematch),
+            1 => (0..3u32, 0..8i32).prop_map(|(rotations, score_target)| Command::SetGameLength { length: GameLength { rotations, score_target } }),
+        ]
+    }
+
+    fn actor() -> impl Strategy<Value = Actor> {
+        prop_oneof![
+            8 => (0..8usize).prop_map(Actor::Seat),
+            1 => Just(Actor::Newcomer),
+            1 => Just(Actor::Stranger),
+        ]
+    }
+
+    fn assert_consistent(state: &RoomState) {
+        if state.players.is_empty() {
+            assert_eq!(state.phase, Phase::Lobby, "an empty room falls back to the lobby");
+        } else {
+            assert!(state.cue_giver_idx < state.players.len(), "cue giver seat exists");
+        }
+        // Stalled: a guessing phase that nobody can finish.
+        let guessed = match state.phase {
+            Phase::Guess1 => Some(&state.guessed_once),
+            Phase::Guess2 => Some(&state.guessed_twice),
+            _ => None,
+        };
+        if let Some(guessed) = guessed {
+            let waiting = state.players.iter().enumerate()
+                .any(|(i, p)| i != state.cue_giver_idx && !guessed.contains(&p.id));
+            assert!(waiting, "{:?} with every guess in", state.phase);
+        }
+        if matches!(state.phase, Phase::Reveal | Phase::GameOver) {
+            assert!(state.target.is_some_and(|t| t < board_size(state)));
+        }
+        assert_eq!(state.standings.is_some(), state.phase == Phase::GameOver, "standings exactly when the game is over");
+    }
+
+    /// Plays one round the way clients do, recording every phase passed through.
+    fn play_round(mut state: RoomState, cells: &[usize], phases: &mut HashSet<&'static str>) -> RoomState {
+        let giver = state.players[state.cue_giver_idx].id;
+        let guessers: Vec<Uuid> = state.players.iter().map(|p| p.id).filter(|id| *id != giver).collect();
+        let target = state.select_options.as_ref().unwrap()[0];
+        let mut step = |state: RoomState, who: Uuid, cmd: Command| {
+            let (state, effects) = reduce(state, who, cmd);
+            assert_eq!(effects, vec![Effect::Broadcast]);
+            phases.insert(state.phase.as_str());
+            state
+        };
+        state = step(state, giver, Command::ChooseTarget { index: target });
+        state = step(state, giver, Command::LockCue1 { cue: "sky".into() });
+        for (g, cell) in guessers.iter().zip(cells.iter().cycle()) {
+            state = step(state, *g, Command::Guess { cell: *cell });
+        }
+        state = step(state, giver, Command::LockCue2 { cue2: "night sky".into() });
+        for (g, cell) in guessers.iter().zip(cells.iter().cycle()) {
+            state = step(state, *g, Command::Guess { cell: *cell });
+        }
+        state
+    }
+
+    proptest! {
+        #[test]
+        fn random_commands_keep_the_room_consistent(
+            players in 0..5usize,
+            seed in any::<u64>(),
+            steps in prop::collection::vec((actor(), command()), 0..120),
+        ) {
+            let mut state = room(players, seed);
+            let mut next_newcomer = 100;
+            for (actor, cmd) in steps {
+                let who = match actor {
+                    Actor::Seat(i) if !state.players.is_empty() => state.players[i % state.players.len()].id,
+                    Actor::Seat(_) | Actor::Newcomer => { next_newcomer += 1; seat_id(next_newcomer) }
+                    Actor::Stranger => Uuid::from_u128(u128::MAX),
+                };
+                let restart = matches!(cmd, Command::StartGame { .. } | Command::Rematch);
+                let before: Vec<(Uuid, i32)> = state.players.iter().map(|p| (p.id, p.score)).collect();
+                let giver_before = state.players.get(state.cue_giver_idx).map(|p| p.id);
+                let was_reveal = state.phase == Phase::Reveal;
+
+                state = reduce(state, who, cmd).0;
+                assert_consistent(&state);
+
+                for p in state.players.iter() {
+                    if let Some((_, old)) = before.iter().find(|(id, _)| *id == p.id) {
+                        if !restart { prop_assert!(p.score >= *old, "score fell mid-game"); }
+                        if state.phase == Phase::Reveal && !was_reveal && Some(p.id) == giver_before {
+                            prop_assert_eq!(p.score, *old, "cue giver scored as a guesser");
+                        }
+                    }
+                }
+            }
+        }
+
+        #[test]
+        fn every_phase_is_reachable(players in 2..7usize, seed in any::<u64>(), cells in prop::collection::vec(0..540usize, 1..6)) {
+            let mut phases = HashSet::new();
+            let length = GameLength { rotations: 2, score_target: 0 };
+            let mut state = reduce(room(players, seed), seat_id(0), Command::SetGameLength { length: length.clone() }).0;
+            phases.insert(state.phase.as_str());
+            state = reduce(state, seat_id(0), Command::StartGame { force: false }).0;
+            let rounds = length.rotations as usize * players;
+            for round in 1..=rounds {
+                state = play_round(state, &cells, &mut phases);
+                if round < rounds {
+                    prop_assert_eq!(state.phase, Phase::Reveal);
+                    state = reduce(state, seat_id(0), Command::NextRound).0;
+                }
+            }
+            prop_assert_eq!(state.phase, Phase::GameOver);
+            prop_assert_eq!(phases.len(), 7, "{:?}", phases);
+        }
+
+        #[test]
+        fn same_seed_same_game(seed in any::<u64>()) {
+            let a = reduce(room(3, seed), seat_id(0), Command::StartGame { force: false }).0;
+            let b = reduce(room(3, seed), seat_id(0), Command::StartGame { force: false }).0;
+            prop_assert_eq!(a.select_options, b.select_options);
+        }
+    }
+
+    #[test]
+    fn last_player_leaving_mid_round_returns_to_lobby() {
+        let mut state = reduce(room(2, 7), seat_id(0), Command::StartGame { force: false }).0;
+        state = reduce(state, seat_id(0), Command::LockCue1 { cue: "sky".into() }).0;
+        state = reduce(state, seat_id(1), Command::Leave).0;
+        assert_eq!(state.phase, Phase::Cue2, "a lone cue giver has nobody to wait for");
+        state = reduce(state, seat_id(0), Command::Leave).0;
+        assert_eq!((state.phase, state.round), (Phase::Lobby, 0));
+    }
+
+    #[test]
+    fn cues_follow_the_room_rules() {
+        let mut phases = HashSet::new();
+        let state = reduce(room(2, 3), seat_id(0), Command::StartGame { force: false }).0;
+        let (state, effects) = reduce(state, seat_id(0), Command::LockCue1 { cue: "red".into() });
+        assert_eq!((state.phase, effects), (Phase::Cue1, vec![Effect::Reject("cues may not name a colour")]));
+        let relaxed = CueRules { forbid_color_names: false, ..CueRules::default() };
+        let (state, effects) = reduce(state, seat_id(0), Command::SetCueRules { rules: relaxed.clone() });
+        assert_eq!(effects, vec![Effect::Reject("wrong phase")], "not in the middle of a round");
+        let state = play_round(state, &[0], &mut phases);
+        assert_eq!(state.phase, Phase::Reveal);
+        let (state, effects) = reduce(state, seat_id(1), Command::SetCueRules { rules: relaxed.clone() });
+        assert_eq!(effects, vec![Effect::Reject("only the host can change the rules")]);
+        let state = reduce(state, seat_id(0), Command::SetCueRules { rules: relaxed }).0;
+        let state = reduce(state, seat_id(0), Command::NextRound).0;
+        let state = reduce(state, seat_id(1), Command::LockCue1 { cue: "red".into() }).0;
+        assert_eq!(state.phase, Phase::Guess1);
+    }
+
+    #[test]
+    fn frame_scoring_pays_the_cue_giver() {
+        let mut phases = HashSet::new();
+        let mut state = reduce(room(3, 5), seat_id(0), Command::SetScoring { scoring: Scoring::Frame }).0;
+        state = reduce(state, seat_id(0), Command::StartGame { force: false }).0;
+        let target = state.select_options.as_ref().unwrap()[0];
+        let state = play_round(state, &[target], &mut phases);
+        assert_eq!(state.players.iter().map(|p| p.score).collect::<Vec<_>>(), vec![2, 3, 3]);
+        let result = state.round_result.unwrap();
+        assert_eq!(result.cue_giver_points, 2);
+        assert_eq!(result.players.iter().map(|p| (p.player, p.points)).collect::<Vec<_>>(), vec![(seat_id(0), 2), (seat_id(1), 3), (seat_id(2), 3)]);
+        assert_eq!((result.players[1].guess, result.players[1].guess_used, result.players[1].distance), (Some(target), Some(GuessUsed::Second), Some(0.0)));
+        assert_eq!(result.players[0].guess, None, "the cue giver has no guess");
+    }
+
+    #[test]
+    fn score_target_ends_the_game_and_rematch_starts_over() {
+        let mut phases = HashSet::new();
+        let mut state = reduce(room(3, 9), seat_id(0), Command::SetGameLength { length: GameLength { rotations: 0, score_target: 3 } }).0;
+        state = reduce(state, seat_id(0), Command::StartGame { force: false }).0;
+        // Seat 1 hits the target, seat 2 lands two cells away.
+        let target = state.select_options.as_ref().unwrap()[0];
+        let near = if target % 30 >= 2 { target - 2 } else { target + 2 };
+        state = play_round(state, &[target, near], &mut phases);
+        assert_eq!(state.phase, Phase::GameOver);
+        let places: Vec<(Uuid, u32, i32)> = state.standings.as_ref().unwrap().iter().map(|s| (s.player, s.place, s.score)).collect();
+        assert_eq!(places, vec![(seat_id(1), 1, 3), (seat_id(2), 2, 1), (seat_id(0), 3, 0)]);
+
+        let (state, effects) = reduce(state, seat_id(1), Command::NextRound);
+        assert_eq!(effects, vec![Effect::Reject("wrong phase")]);
+        let state = reduce(state, seat_id(2), Command::Rematch).0;
+        assert_eq!((state.phase, state.round, state.players.len()), (Phase::Cue1, 1, 3));
+        assert!(state.players.iter().all(|p| p.score == 0) && state.standings.is_none());
+        assert_eq!(state.game_length.score_target, 3, "settings carry over");
+    }
+
+    #[test]
+    fn rotations_count_the_table_the_game_started_with() {
+        let mut phases = HashSet::new();
+        let length = GameLength { rotations: 1, score_target: 0 };
+        let mut state = reduce(room(2, 10), seat_id(0), Command::SetGameLength { length: length.clone() }).0;
+        state = reduce(state, seat_id(0), Command::StartGame { force: false }).0;
+        state = reduce(state, seat_id(2), Command::Join { name: "late".into(), user_id: None, avatar: None, bot: false }).0;
+        state = play_round(state, &[0], &mut phases);
+        state = reduce(state, seat_id(0), Command::NextRound).0;
+        state = play_round(state, &[0], &mut phases);
+        assert_eq!((state.phase, state.round), (Phase::GameOver, 2), "a latecomer does not add turns");
+
+        let mut state = reduce(room(3, 11), seat_id(0), Command::SetGameLength { length }).0;
+        state = reduce(state, seat_id(0), Command::StartGame { force: false }).0;
+        state = play_round(state, &[0], &mut phases);
+        state = reduce(state, seat_id(2), Command::Leave).0;
+        state = reduce(state, seat_id(0), Command::NextRound).0;
+        state = play_round(state, &[0], &mut phases);
+        assert_eq!(state.phase, Phase::Reveal, "someone leaving does not cut the game short");
+        state = reduce(state, seat_id(0), Command::NextRound).0;
+        state = play_round(state, &[0], &mut phases);
+        assert_eq!((state.phase, state.round), (Phase::GameOver, 3));
+    }
+
+    #[test]
+    fn standings_break_ties_on_bullseyes_then_share() {
+        let player = |i: usize, score, bullseyes| Player { id: seat_id(i), name: format!("p{}", i), score, bullseyes, ready: true, user_id: None, avatar: None, bot: false };
+        let table = standings(&[player(0, 5, 0), player(1, 5, 1), player(2, 5, 0), player(3, 2, 0)]);
+        let places: Vec<(Uuid, u32)> = table.iter().map(|s| (s.player, s.place)).collect();
+        assert_eq!(places, vec![(seat_id(1), 1), (seat_id(0), 2), (seat_id(2), 2), (seat_id(3), 4)]);
+    }
+
+    #[test]
+    fn start_waits_for_enough_ready_players_unless_forced() {
+        let (state, effects) = reduce(room(1, 2), seat_id(0), Command::StartGame { force: true });
+        assert_eq!((state.phase, effects), (Phase::Lobby, vec![Effect::Reject("not enough players")]));
+        let state = reduce(state, seat_id(1), Command::Join { name: "late".into(), user_id: None, avatar: None, bot: false }).0;
+        assert_eq!(start_blockers(&state), vec!["not everyone is ready"]);
+        let (state, effects) = reduce(state, seat_id(0), Command::StartGame { force: false });
+        assert_eq!(effects, vec![Effect::Reject("not everyone is ready")]);
+        let (state, effects) = reduce(state, seat_id(1), Command::StartGame { force: true });
+        assert_eq!(effects, vec![Effect::Reject("only the host can force a start")]);
+        let state = reduce(state, seat_id(0), Command::StartGame { force: true }).0;
+        assert_eq!(state.phase, Phase::Cue1);
+    }
+
+    #[test]
+    fn no_restart_in_the_middle_of_a_game() {
+        let mut state = reduce(room(2, 6), seat_id(0), Command::StartGame { force: false }).0;
+        state = reduce(state, seat_id(0), Command::LockCue1 { cue: "sky".into() }).0;
+        state = reduce(state, seat_id(1), Command::Guess { cell: 0 }).0;
+        assert_eq!(state.phase, Phase::Cue2);
+        state = reduce(state, seat_id(0), Command::LockCue2 { cue2: "night sky".into() }).0;
+        assert_eq!(state.phase, Phase::Guess2);
+        for force in [false, true] {
+            let (after, effects) = reduce(state, seat_id(0), Command::StartGame { force });
+            assert_eq!((after.phase, after.round, effects), (Phase::Guess2, 1, vec![Effect::Reject("wrong phase")]));
+            state = after;
+        }
+    }
+
+    #[test]
+    fn passing_the_second_cue_reveals_the_first_guesses() {
+        let mut state = reduce(room(3, 8), seat_id(0), Command::StartGame { force: false }).0;
+        let (after, effects) = reduce(state, seat_id(1), Command::PassCue);
+        assert_eq!(effects, vec![Effect::Reject("only the cue giver can pass")]);
+        state = reduce(after, seat_id(0), Command::LockCue1 { cue: "sky".into() }).0;
+        state = reduce(state, seat_id(1), Command::Guess { cell: 3 }).0;
+        state = reduce(state, seat_id(2), Command::Guess { cell: 4 }).0;
+        state = reduce(state, seat_id(0), Command::PassCue).0;
+        assert_eq!(state.phase, Phase::Reveal);
+        let result = state.round_result.unwrap();
+        assert_eq!(result.players.iter().map(|p| p.guess_used).collect::<Vec<_>>(), vec![None, Some(GuessUsed::First), Some(GuessUsed::First)]);
+    }
+
+    #[test]
+    fn full_rooms_turn_players_away() {
+        let limits = PlayerLimits { min: 2, max: 2 };
+        let state = reduce(room(2, 4), seat_id(0), Command::SetPlayerLimits { limits }).0;
+        let (state, effects) = reduce(state, seat_id(2), Command::Join { name: "third".into(), user_id: None, avatar: None, bot: false });
+        assert_eq!((state.players.len(), effects), (2, vec![Effect::Reject("room is full")]));
+        let (_, effects) = reduce(state, seat_id(0), Command::SetPlayerLimits { limits: PlayerLimits { min: 1, max: 2 } });
+        assert_eq!(effects, vec![Effect::Reject("player limits out of range")]);
+    }
+
+    #[test]
+    fn exact_guess_scores_three() {
+        let mut phases = HashSet::new();
+        let state = reduce(room(2, 1), seat_id(0), Command::StartGame { force: false }).0;
+        let target = state.select_options.as_ref().unwrap()[0];
+        let state = play_round(state, &[target], &mut phases);
+        assert_eq!(state.players.iter().map(|p| p.score).collect::<Vec<_>>(), vec![0, 3]);
+    }
+}
@@ -0,0 +1,194 @@
+//! Colour words in English and Russian with a representative colour each, and fuzzy lookup of typed words.
+
+use crate::color::{rgb_to_lab, Lab, Rgb};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum Lang { #[default] En, Ru }
+
+#[derive(Debug)]
+pub(crate) struct ColorWord {
+    pub en: &'static str,
+    pub ru: &'static str,
+    pub rgb: Rgb,
+    /// A plain colour name ("red") rather than a thing with a colour ("tomato").
+    pub basic: bool,
+}
+
+impl ColorWord {
+    pub(crate) fn word(&self, lang: Lang) -> &'static str {
+        match lang { Lang::En => self.en, Lang::Ru => self.ru }
+    }
+
+    pub(crate) fn lab(&self) -> Lab {
+        rgb_to_lab(self.rgb)
+    }
+}
+
+const fn basic(en: &'static str, ru: &'static str, rgb: Rgb) -> ColorWord { ColorWord { en, ru, rgb, basic: true } }
+const fn thing(en: &'static str, ru: &'static str, rgb: Rgb) -> ColorWord { ColorWord { en, ru, rgb, basic: false } }
+
+pub(crate) const WORDS: &[ColorWord] = &[
+    basic("red", "красный", [220, 30, 35]),
+    basic("orange", "оранжевый", [255, 140, 0]),
+    basic("yellow", "жёлтый", [250, 225, 20]),
+    basic("green", "зелёный", [40, 170, 60]),
+    basic("cyan", "голубой", [40, 180, 235]),
+    basic("blue", "синий", [30, 70, 210]),
+    basic("violet", "фиолетовый", [130, 50, 200]),
+    basic("purple", "пурпурный", [150, 30, 140]),
+    basic("pink", "розовый", [245, 130, 180]),
+    basic("brown", "коричневый", [130, 75, 30]),
+    basic("black", "чёрный", [20, 20, 20]),
+    basic("white", "белый", [245, 245, 245]),
+    basic("gray", "серый", [128, 128, 128]),
+    thing("tomato", "томат", [255, 99, 71]),
+    thing("cherry", "вишня", [160, 15, 45]),
+    thing("blood", "кровь", [138, 7, 7]),
+    thing("wine", "вино", [114, 47, 55]),
+    thing("brick", "кирпич", [170, 60, 40]),
+    thing("rust", "ржавчина", [183, 65, 14]),
+    thing("fire", "огонь", [226, 88, 34]),
+    thing("carrot", "морковь", [237, 145, 33]),
+    thing("pumpkin", "тыква", [255, 117, 24]),
+    thing("tangerine", "мандарин", [255, 160, 40]),
+    thing("peach", "персик", [255, 203, 164]),
+    thing("salmon", "лосось", [250, 128, 114]),
+    thing("coral", "коралл", [255, 127, 80]),
+    thing("sunset", "закат", [250, 110, 80]),
+    thing("sand", "песок", [194, 178, 128]),
+    thing("mustard", "горчица", [225, 173, 1]),
+    thing("honey", "мёд", [235, 170, 30]),
+    thing("gold", "золото", [212, 175, 55]),
+    thing("sun", "солнце", [255, 200, 0]),
+    thing("lemon", "лимон", [255, 244, 79]),
+    thing("banana", "банан", [255, 225, 53]),
+    thing("olive", "оливка", [128, 128, 0]),
+    thing("lime", "лайм", [170, 220, 50]),
+    thing("grass", "трава", [86, 170, 48]),
+    thing("leaf", "лист", [60, 140, 40]),
+    thing("moss", "мох", [110, 120, 50]),
+    thing("forest", "лес", [34, 100, 34]),
+    thing("pine", "хвоя", [1, 100, 70]),
+    thing("mint", "мята", [152, 230, 180]),
+    thing("emerald", "изумруд", [0, 155, 119]),
+    thing("jade", "нефрит", [0, 168, 107]),
+    thing("turquoise", "бирюза", [64, 224, 208]),
+    thing("sea", "море", [0, 105, 148]),
+    thing("ocean", "океан", [0, 80, 140]),
+    thing("sky", "небо", [135, 200, 235]),
+    thing("ice", "лёд", [200, 235, 245]),
+    thing("cornflower", "василёк", [100, 149, 237]),
+    thing("denim", "джинса", [21, 96, 189]),
+    thing("sapphire", "сапфир", [15, 82, 186]),
+    thing("night", "ночь", [25, 25, 80]),
+    thing("lavender", "лаванда", [181, 126, 220]),
+    thing("lilac", "сирень", [200, 162, 200]),
+    thing("grape", "виноград", [111, 45, 168]),
+    thing("amethyst", "аметист", [153, 102, 204]),
+    thing("plum", "слива", [142, 69, 133]),
+    thing("eggplant", "баклажан", [97, 64, 81]),
+    thing("fuchsia", "фуксия", [255, 0, 255]),
+    thing("raspberry", "малина", [227, 11, 92]),
+    thing("rose", "роза", [255, 0, 127]),
+    thing("flamingo", "фламинго", [252, 142, 172]),
+    thing("chocolate", "шоколад", [123, 63, 0]),
+    thing("coffee", "кофе", [111, 78, 55]),
+    thing("caramel", "карамель", [196, 130, 60]),
+    thing("cream", "сливки", [255, 253, 208]),
+    thing("milk", "молоко", [253, 255, 245]),
+    thing("ash", "пепел", [178, 190, 181]),
+    thing("coal", "уголь", [40, 40, 40]),
+];
+
+/// Shade words: they shift lightness instead of naming a colour.
+pub(crate) const LIGHTER: &[&str] = &["light", "pale", "bright", "светлый", "бледный", "яркий", "светло"];
+pub(crate) const DARKER: &[&str] = &["dark", "deep", "тёмный", "тёмно", "глубокий"];
+
+/// Lowercases, folds ё into е and keeps letters only.
+pub(crate) fn normalize(word: &str) -> String {
+    word.chars()
+        .flat_map(char::to_lowercase)
+        .map(|c| if c == 'ё' { 'е' } else { c })
+        .filter(|c| c.is_alphabetic())
+        .collect()
+}
+
+/// Splits a cue into normalised words, treating hyphens as spaces ("тёмно-синий").
+pub(crate) fn words(cue: &str) -> Vec<String> {
+    cue.split(|c: char| c.is_whitespace() || c == '-')
+        .map(normalize)
+        .filter(|w| !w.is_empty())
+        .collect()
+}
+
+fn levenshtein(a: &[char], b: &[char]) -> usize {
+    let mut prev: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.iter().enumerate() {
+        let mut cur = vec![i + 1; b.len() + 1];
+        for (j, cb) in b.iter().enumerate() {
+            cur[j + 1] = (prev[j + 1] + 1).min(cur[j] + 1).min(prev[j] + usize::from(ca != cb));
+        }
+        prev = cur;
+    }
+    prev[b.len()]
+}
+
+/// Whether a typed word is close enough to a lexicon word: a small typo, or an inflected form sharing most of its stem.
+pub(crate) fn similar(typed: &str, known: &str) -> bool {
+    let a: Vec<char> = typed.chars().collect();
+    let b: Vec<char> = normalize(known).chars().collect();
+    if a == b { return true; }
+    let shorter = a.len().min(b.len());
+    let allowed = match shorter { 0..=3 => 0, 4..=6 => 1, _ => 2 };
+    if allowed > 0 && levenshtein(&a, &b) <= allowed { return true; }
+    let prefix = a.iter().zip(b.iter()).take_while(|(x, y)| x == y).count();
+    prefix >= 4 && prefix * 5 >= shorter * 3
+}
+
+/// The lexicon entry a typed word most likely means, in either language.
+pub(crate) fn lookup(word: &str) -> Option<&'static ColorWord> {
+    let word = normalize(word);
+    if word.is_empty() { return None; }
+    WORDS.iter().find(|w| normalize(w.en) == word || normalize(w.ru) == word)
+        .or_else(|| WORDS.iter().find(|w| similar(&word, w.en) || similar(&word, w.ru)))
+}
+
+pub(crate) fn is_shade(word: &str, shades: &[&str]) -> bool {
+    let word = normalize(word);
+    shades.iter().any(|s| similar(&word, s))
+}
+
+/// What a cue points at: the mean of its colour words, moved lighter or darker by shade words.
+pub(crate) fn interpret(cues: &[&str]) -> Option<Lab> {
+    let mut colors = Vec::new();
+    let mut shift = 0.0;
+    for cue in cues {
+        for w in words(cue) {
+            if is_shade(&w, LIGHTER) { shift += 15.0; }
+            else if is_shade(&w, DARKER) { shift -= 15.0; }
+            else if let Some(entry) = lookup(&w) { colors.push(entry.lab()); }
+        }
+    }
+    Lab::mean(&colors).map(|lab| Lab { l: (lab.l + shift).clamp(0.0, 100.0), ..lab })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lookup_tolerates_typos_and_inflection() {
+        assert_eq!(lookup("Tomatoe").map(|w| w.en), Some("tomato"));
+        assert_eq!(lookup("малиновый").map(|w| w.en), Some("raspberry"));
+        assert_eq!(lookup("ЗЕЛЕНЫЙ").map(|w| w.en), Some("green"));
+        assert!(lookup("table").is_none());
+    }
+
+    #[test]
+    fn shades_move_lightness() {
+        let plain = interpret(&["sky"]).unwrap();
+        let dark = interpret(&["тёмно-небо"]).unwrap();
+        assert!(dark.l < plain.l);
+    }
+}
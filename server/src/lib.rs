@@ -0,0 +1,3109 @@
+use std::{collections::{HashMap, HashSet}, net::{IpAddr, SocketAddr}, sync::Arc, time::{Duration, Instant}};
+
+use axum::{
+    body::Bytes,
+    extract::{ConnectInfo, DefaultBodyLimit, Path, State, ws::{Message, WebSocket, WebSocketUpgrade}},
+    http::{header, HeaderMap, HeaderValue, Method, StatusCode},
+    response::IntoResponse,
+    routing::{delete, get, post, put},
+    Json, Router,
+};
+use futures_util::{SinkExt, StreamExt};
+use serde::{Deserialize, Serialize};
+use tokio_stream::wrappers::UnboundedReceiverStream;
+use tower_http::{cors::CorsLayer, services::{ServeDir, ServeFile}, compression::CompressionLayer, set_header::SetResponseHeaderLayer, trace::TraceLayer};
+use tracing::{info, Instrument};
+use uuid::Uuid;
+
+use sqlx::SqlitePool;
+
+mod board;
+mod bots;
+mod color;
+mod cues;
+mod game;
+mod lexicon;
+mod scoring;
+
+pub use board::Gradient;
+pub use bots::BotDifficulty;
+pub use cues::CueRules;
+pub use lexicon::Lang;
+pub use game::{GameLength, PlayerLimits, Standing};
+pub use scoring::{GuessUsed, PlayerRound, RoundResult, Scoring};
+
+// ===================== Config =====================
+#[derive(Clone)]
+pub struct AppConfig {
+    pub bind_addr: SocketAddr,
+    pub jwt_secret: String,
+    pub admin_secret: String,
+    pub static_dir: String,
+    pub data_dir: String,
+    /// Peers whose `X-Forwarded-For` is believed, e.g. the local nginx.
+    pub trusted_proxies: Vec<IpAddr>,
+    /// Cost of new password hashes; stored hashes below it are upgraded at login.
+    pub argon2_params: argon2::Params,
+    /// Serve `/metrics` here instead of on the public listener.
+    pub metrics_bind: Option<SocketAddr>,
+}
+
+impl AppConfig {
+    pub fn from_env() -> anyhow::Result<Self> {
+        let bind = std::env::var("BIND").unwrap_or_else(|_| "0.0.0.0:8765".into());
+        let bind_addr: SocketAddr = bind.parse()?;
+        let jwt_secret = std::env::var("JWT_SECRET").unwrap_or_else(|_| "dev-secret-change-me".into());
+        let admin_secret = std::env::var("ADMIN_SECRET").unwrap_or_else(|_| "dev-admin-change-me".into());
+        let static_dir = std::env::var("STATIC_DIR").unwrap_or_else(|_| "frontend".into());
+        let data_dir = std::env::var("DATA_DIR").unwrap_or_else(|_| "data".into());
+        let trusted_proxies = std::env::var("TRUSTED_PROXIES").unwrap_or_default()
+            .split(',')
+            .map(str::trim)
+            .filter(|s| !s.is_empty())
+            .map(|s| s.parse::<IpAddr>())
+            .collect::<Result<Vec<_>, _>>()?;
+        let env_u32 = |name: &str, default: u32| -> anyhow::Result<u32> {
+            match std::env::var(name) {
+                Ok(v) => v.parse().map_err(|e| anyhow::anyhow!("{}: {}", name, e)),
+                Err(_) => Ok(default),
+            }
+        };
+        let argon2_params = argon2::Params::new(
+            env_u32("ARGON2_MEMORY_KIB", argon2::Params::DEFAULT_M_COST)?,
+            env_u32("ARGON2_TIME_COST", argon2::Params::DEFAULT_T_COST)?,
+            env_u32("ARGON2_PARALLELISM", argon2::Params::DEFAULT_P_COST)?,
+            None,
+        ).map_err(|e| anyhow::anyhow!("invalid argon2 settings: {}", e))?;
+        let metrics_bind = match std::env::var("METRICS_BIND") {
+            Ok(v) if !v.trim().is_empty() => Some(v.trim().parse()?),
+            _ => None,
+        };
+        Ok(Self { bind_addr, jwt_secret, admin_secret, static_dir, data_dir, trusted_proxies, argon2_params, metrics_bind })
+    }
+}
+
+// ===================== Logging =====================
+/// `RUST_LOG` filters (default `info`); `LOG_FORMAT=json` switches to one JSON object per line;
+/// `LOG_FILE` writes there instead of stdout, rotating at `LOG_FILE_MAX_BYTES` and keeping `LOG_FILE_KEEP` old files.
+pub fn init_logging() -> anyhow::Result<()> {
+    use tracing_subscriber::{fmt::writer::BoxMakeWriter, EnvFilter};
+    let filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info"));
+    let json = match std::env::var("LOG_FORMAT").as_deref() {
+        Ok("json") => true,
+        Ok("text") | Ok("") | Err(_) => false,
+        Ok(other) => anyhow::bail!("LOG_FORMAT: expected text or json, got {}", other),
+    };
+    let file = std::env::var("LOG_FILE").ok().filter(|p| !p.trim().is_empty());
+    let writer = match &file {
+        Some(path) => {
+            let max_bytes = match std::env::var("LOG_FILE_MAX_BYTES") {
+                Ok(v) => v.parse().map_err(|e| anyhow::anyhow!("LOG_FILE_MAX_BYTES: {}", e))?,
+                Err(_) => 10 * 1024 * 1024,
+            };
+            let keep = match std::env::var("LOG_FILE_KEEP") {
+                Ok(v) => v.parse().map_err(|e| anyhow::anyhow!("LOG_FILE_KEEP: {}", e))?,
+                Err(_) => 5,
+            };
+            let file = RotatingFile::open(path.into(), max_bytes, keep)
+                .map_err(|e| anyhow::anyhow!("LOG_FILE {}: {}", path, e))?;
+            BoxMakeWriter::new(std::sync::Mutex::new(file))
+        }
+        None => BoxMakeWriter::new(std::io::stdout),
+    };
+    let builder = tracing_subscriber::fmt().with_env_filter(filter).with_writer(writer).with_ansi(file.is_none());
+    if json { builder.json().init() } else { builder.init() }
+    Ok(())
+}
+
+/// Log file that is renamed to `<path>.1` (older ones shifted up to `<path>.<keep>`) once it would exceed `max_bytes`.
+struct RotatingFile {
+    path: std::path::PathBuf,
+    file: std::fs::File,
+    size: u64,
+    max_bytes: u64,
+    keep: usize,
+}
+
+impl RotatingFile {
+    fn open(path: std::path::PathBuf, max_bytes: u64, keep: usize) -> std::io::Result<Self> {
+        if let Some(dir) = path.parent().filter(|d| !d.as_os_str().is_empty()) {
+            std::fs::create_dir_all(dir)?;
+        }
+        let file = std::fs::OpenOptions::new().create(true).append(true).open(&path)?;
+        let size = file.metadata()?.len();
+        Ok(Self { path, file, size, max_bytes, keep })
+    }
+
+    fn rotated(&self, n: usize) -> std::path::PathBuf {
+        let mut name = self.path.clone().into_os_string();
+        name.push(format!(".{}", n));
+        name.into()
+    }
+
+    fn rotate(&mut self) -> std::io::Result<()> {
+        use std::io::Write;
+        self.file.flush()?;
+        if self.keep == 0 {
+            self.file = std::fs::OpenOptions::new().create(true).write(true).truncate(true).open(&self.path)?;
+        } else {
+            for n in (1..self.keep).rev() {
+                let from = self.rotated(n);
+                if from.exists() { std::fs::rename(&from, self.rotated(n + 1))?; }
+            }
+            std::fs::rename(&self.path, self.rotated(1))?;
+            self.file = std::fs::OpenOptions::new().create(true).append(true).open(&self.path)?;
+        }
+        self.size = 0;
+        Ok(())
+    }
+}
+
+impl std::io::Write for RotatingFile {
+    // The fmt layer writes each event with a single call, so lines are never split across files.
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        if self.size > 0 && self.size + buf.len() as u64 > self.max_bytes {
+            self.rotate()?;
+        }
+        let n = self.file.write(buf)?;
+        self.size += n as u64;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.file.flush()
+    }
+}
+
+// ===================== Auth Models =====================
+#[derive(Deserialize)]
+struct AuthPayload { username: String, password: String }
+
+/// One entry of the pre-server `localStorage('accounts')` list, as found in the operator's export.
+#[derive(Debug, Deserialize)]
+struct LegacyAccount {
+    username: String,
+    /// Hex SHA-256 of the password, as `sha256()` in the old `hub.js` produced it.
+    #[serde(rename = "passwordHash", alias = "password_hash")]
+    password_hash: String,
+}
+
+/// What a player sends to import a legacy account: its name and plain password, checked against the export.
+#[derive(Deserialize)]
+struct LegacyImportPayload { username: String, password: String }
+
+#[derive(Serialize)]
+struct AuthResponse { token: String, user: PublicUser }
+
+#[derive(Serialize, Deserialize, Clone)]
+struct PublicUser { id: Uuid, username: String, avatar: Option<String> }
+
+impl PublicUser {
+    /// Users without an uploaded picture get the generated one.
+    fn new(id: Uuid, username: String, avatar: Option<String>) -> Self {
+        let avatar = avatar.or_else(|| Some(default_avatar_url(&username)));
+        Self { id, username, avatar }
+    }
+}
+
+/// What anyone can see about an account at `GET /api/users/{id}`.
+#[derive(Serialize, sqlx::FromRow)]
+struct PublicProfile {
+    id: String,
+    username: String,
+    display_name: Option<String>,
+    bio: Option<String>,
+    avatar: Option<String>,
+    games_played: i64,
+    wins: i64,
+}
+
+/// `PATCH /api/me`. Absent fields are left alone; an empty string clears the field.
+#[derive(Deserialize)]
+struct UpdateProfilePayload { display_name: Option<String>, bio: Option<String> }
+
+const MAX_DISPLAY_NAME_CHARS: usize = 32;
+const MAX_BIO_CHARS: usize = 280;
+
+/// Current layout of the settings document. Bump it when a key changes meaning;
+/// purely additive keys only need a `#[serde(default)]`.
+const SETTINGS_VERSION: u32 = 1;
+
+/// Per-user preferences synced between the browser and the desktop client.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct UserSettings {
+    version: u32,
+    language: String,
+    theme: String,
+    sound_enabled: bool,
+    sound_volume: u8,
+    default_nickname: Option<String>,
+}
+
+impl Default for UserSettings {
+    fn default() -> Self {
+        Self {
+            version: SETTINGS_VERSION,
+            language: "ru".into(),
+            theme: "dark".into(),
+            sound_enabled: true,
+            sound_volume: 80,
+            default_nickname: None,
+        }
+    }
+}
+
+impl UserSettings {
+    fn validate(&self) -> Result<(), &'static str> {
+        if self.version > SETTINGS_VERSION { return Err("unsupported_settings_version"); }
+        if !matches!(self.language.as_str(), "ru" | "en") { return Err("invalid_language"); }
+        if !matches!(self.theme.as_str(), "dark" | "light" | "system") { return Err("invalid_theme"); }
+        if self.sound_volume > 100 { return Err("invalid_sound_volume"); }
+        if let Some(nick) = &self.default_nickname {
+            if nick.trim().is_empty() || nick.chars().count() > MAX_DISPLAY_NAME_CHARS { return Err("invalid_default_nickname"); }
+        }
+        Ok(())
+    }
+
+    /// Brings a document written under an older `version` up to date.
+    fn upgrade(mut self) -> Self {
+        // Only v1 exists so far; conversions for later versions go here, oldest first.
+        self.version = SETTINGS_VERSION;
+        self
+    }
+}
+
+#[derive(Deserialize)]
+struct ChangePasswordPayload { old_password: String, new_password: String }
+
+#[derive(Deserialize)]
+struct ChangeUsernamePayload { username: String }
+
+#[derive(Deserialize)]
+struct DeleteAccountPayload { password: String }
+
+#[derive(sqlx::FromRow)]
+struct UserRow { id: String, username: String, pwd_hash: String, avatar: Option<String> }
+
+#[derive(Serialize, sqlx::FromRow)]
+struct FriendEntry { id: String, username: String, avatar: Option<String>, since: i64 }
+
+#[derive(Serialize)]
+struct FriendsResponse {
+    friends: Vec<FriendEntry>,
+    incoming: Vec<FriendEntry>,
+    outgoing: Vec<FriendEntry>,
+    blocked: Vec<FriendEntry>,
+}
+
+#[derive(Deserialize)]
+struct FriendRequestPayload { username: String }
+
+/// Name left on match history rows after their account is deleted.
+const DELETED_USER_NAME: &str = "deleted user";
+
+// ===================== WS Models (match frontend) =====================
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ClientMsg {
+    /// `token` is the optional account JWT; with it the player shows up with the account's avatar.
+    /// `private` only applies when the room is created by this join: such rooms are entered by invite.
+    /// It is refused for the `PUBLIC_ROOMS` the bundled frontends join by name.
+    /// `board`, also only applied on creation and ignored for `PUBLIC_ROOMS`, is a board id such as `oklch-30x18`; the classic HSL board otherwise.
+    Join { name: String, room: Option<String>, token: Option<String>, private: Option<bool>, #[serde(default)] board: Option<String> },
+    /// Signs the connection in for presence and invites without joining a room.
+    Identify { token: String },
+    /// Invites an online friend (by account id) to the sender's room.
+    Invite { user_id: Uuid },
+    AcceptInvite { invite_id: Uuid, name: Option<String> },
+    DeclineInvite { invite_id: Uuid },
+    /// Starts once enough players are seated and all are ready; the host may `force` it past the ready check.
+    StartGame { #[serde(default)] force: bool },
+    /// Marks the sender ready (or not) to start, in the lobby or after a game.
+    SetReady { ready: bool },
+    LockCue1 { cue: String },
+    LockCue2 { cue2: String },
+    /// The cue giver skips the cue; the round is revealed with the guesses made so far.
+    PassCue,
+    Guess { cell: usize },
+    NextRound,
+    ChooseTarget { index: usize },
+    /// Replaces the room's cue rules; host only (the longest-seated player).
+    SetCueRules { rules: CueRules },
+    /// Picks how guesses are scored; host only, in the lobby or at reveal.
+    SetScoring { scoring: Scoring },
+    /// Sets when games end; host only, in the lobby or after a game.
+    SetGameLength { length: GameLength },
+    /// Narrows how many players the room takes and needs, within the game's own bounds; host only.
+    SetPlayerLimits { limits: PlayerLimits },
+    /// Starts a new game with the same players and settings once the last one is over.
+    Rematch,
+    /// Seats a bot in the sender's room; it guesses, and gives cues when its turn comes.
+    AddBot { difficulty: Option<BotDifficulty>, lang: Option<Lang> },
+    /// Removes a bot (by player id) from the sender's room.
+    RemoveBot { player: Uuid },
+    // Admin (optional)
+    AdminReset { secret: String },
+    AdminKick { secret: String, player: Uuid },
+}
+
+impl ClientMsg {
+    /// Wire `type`, used as a metrics label.
+    fn kind(&self) -> &'static str {
+        match self {
+            ClientMsg::Join { .. } => "join",
+            ClientMsg::Identify { .. } => "identify",
+            ClientMsg::Invite { .. } => "invite",
+            ClientMsg::AcceptInvite { .. } => "accept_invite",
+            ClientMsg::DeclineInvite { .. } => "decline_invite",
+            ClientMsg::StartGame { .. } => "start_game",
+            ClientMsg::SetReady { .. } => "set_ready",
+            ClientMsg::LockCue1 { .. } => "lock_cue1",
+            ClientMsg::LockCue2 { .. } => "lock_cue2",
+            ClientMsg::PassCue => "pass_cue",
+            ClientMsg::Guess { .. } => "guess",
+            ClientMsg::NextRound => "next_round",
+            ClientMsg::ChooseTarget { .. } => "choose_target",
+            ClientMsg::SetCueRules { .. } => "set_cue_rules",
+            ClientMsg::SetScoring { .. } => "set_scoring",
+            ClientMsg::SetGameLength { .. } => "set_game_length",
+            ClientMsg::SetPlayerLimits { .. } => "set_player_limits",
+            ClientMsg::Rematch => "rematch",
+            ClientMsg::AddBot { .. } => "add_bot",
+            ClientMsg::RemoveBot { .. } => "remove_bot",
+            ClientMsg::AdminReset { .. } => "admin_reset",
+            ClientMsg::AdminKick { .. } => "admin_kick",
+        }
+    }
+}
+
+/// A [`ClientMsg`] as it travels over the socket, with optional delivery fields next to `type`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ClientEnvelope {
+    /// Chosen by the client. The server answers it with `ack` or `reject` instead of `error`, and a message
+    /// repeating a recent id of the same client session is not carried out again, only answered again (see `reply_key`).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub msg_id: Option<String>,
+    /// A random id the client keeps for as long as one tab or window lives, so that ids resent over a new
+    /// connection are still recognised. Without it ids are only remembered per connection.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub session: Option<String>,
+    /// Game commands are refused as stale once the room has left this round...
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expect_round: Option<u32>,
+    /// ...or this phase (the `phase` string of the state).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expect_phase: Option<String>,
+    #[serde(flatten)]
+    pub msg: ClientMsg,
+}
+
+/// The room state a client's command was meant for (see [`ClientEnvelope`]).
+#[derive(Debug, Default)]
+struct Expect { round: Option<u32>, phase: Option<String> }
+
+/// Answers to message ids a client session sent lately, so retries and double clicks are answered, not repeated.
+/// An id whose message is still running has no answer yet.
+#[derive(Debug)]
+struct RecentReplies { replies: std::collections::VecDeque<(String, Option<Result<(), String>>)>, last_used: Instant }
+
+impl Default for RecentReplies {
+    fn default() -> Self {
+        Self { replies: Default::default(), last_used: Instant::now() }
+    }
+}
+
+impl RecentReplies {
+    const KEEP: usize = 64;
+    /// Long enough for a client to notice a dropped connection, reconnect and resend.
+    const TTL: Duration = Duration::from_secs(10 * 60);
+
+    fn get(&self, msg_id: &str) -> Option<&Option<Result<(), String>>> {
+        self.replies.iter().find(|(id, _)| id == msg_id).map(|(_, r)| r)
+    }
+
+    /// Claims `msg_id` before its message runs, so a copy arriving meanwhile is not run as well.
+    fn reserve(&mut self, msg_id: String) {
+        if self.replies.len() == Self::KEEP { self.replies.pop_front(); }
+        self.replies.push_back((msg_id, None));
+        self.last_used = Instant::now();
+    }
+
+    fn finish(&mut self, msg_id: &str, reply: Result<(), String>) {
+        match self.replies.iter_mut().find(|(id, _)| id == msg_id) {
+            Some((_, slot)) => *slot = Some(reply),
+            None => {
+                self.reserve(msg_id.to_string());
+                self.replies.back_mut().unwrap().1 = Some(reply);
+            }
+        }
+        self.last_used = Instant::now();
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ServerMsg {
+    Welcome { id: Uuid, room: String },
+    State { state: Box<GameStateDto> },
+    Error { message: String },
+    /// The message with this `msg_id` was carried out.
+    Ack { msg_id: String },
+    /// The message with this `msg_id` was refused, e.g. `stale command` or a rule it broke.
+    Reject { msg_id: String, reason: String },
+    /// Full friend presence list, sent when the connection signs in or the friend list changes.
+    Presence { friends: Vec<FriendPresence> },
+    PresenceUpdate { presence: FriendPresence },
+    Invite { invite_id: Uuid, from: Uuid, from_name: String, room: String },
+    InviteDeclined { invite_id: Uuid, by: Uuid },
+}
+
+impl ServerMsg {
+    /// Wire `type`, used as a metrics label.
+    fn kind(&self) -> &'static str {
+        match self {
+            ServerMsg::Welcome { .. } => "welcome",
+            ServerMsg::State { .. } => "state",
+            ServerMsg::Error { .. } => "error",
+            ServerMsg::Ack { .. } => "ack",
+            ServerMsg::Reject { .. } => "reject",
+            ServerMsg::Presence { .. } => "presence",
+            ServerMsg::PresenceUpdate { .. } => "presence_update",
+            ServerMsg::Invite { .. } => "invite",
+            ServerMsg::InviteDeclined { .. } => "invite_declined",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FriendPresence {
+    pub user_id: Uuid,
+    pub online: bool,
+    pub room: Option<String>,
+    /// In a room whose game has started, as opposed to waiting in its lobby.
+    pub in_game: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PlayerDto {
+    pub id: Uuid,
+    pub name: String,
+    pub score: i32,
+    pub avatar: Option<String>,
+    pub user_id: Option<Uuid>,
+    /// Whether this player is a friend of the client receiving the state.
+    #[serde(default)]
+    pub friend: bool,
+    #[serde(default)]
+    pub bot: bool,
+    #[serde(default)]
+    pub ready: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GameStateDto {
+    pub room: String,
+    pub game: GameKind,
+    pub round: u32,
+    pub cols: u32,
+    pub rows: u32,
+    /// Names the cell colours; the palette is served at `/api/boards/{board_id}`.
+    pub board_id: String,
+    pub cue_giver: Option<Uuid>,
+    /// May change the room's rules.
+    #[serde(default)]
+    pub host: Option<Uuid>,
+    pub phase: String,
+    pub cue1: Option<String>,
+    pub cue2: Option<String>,
+    pub target: Option<usize>,
+    pub select_options: Option<Vec<usize>>,
+    #[serde(default)]
+    pub cue_rules: CueRules,
+    #[serde(default)]
+    pub scoring: Scoring,
+    /// Set at reveal: each player's scored guess, its distance and the points it earned, so clients need not recompute them.
+    #[serde(default)]
+    pub round_result: Option<RoundResult>,
+    #[serde(default)]
+    pub game_length: GameLength,
+    /// Final places, set once the game is over (phase `game_over`).
+    #[serde(default)]
+    pub standings: Option<Vec<Standing>>,
+    pub player_limits: PlayerLimits,
+    /// Why `start_game` would be refused right now, in the lobby or after a game; empty when it would start.
+    #[serde(default)]
+    pub start_blockers: Vec<String>,
+    pub players: Vec<PlayerDto>,
+    pub guessed_once: HashSet<Uuid>,
+    pub guessed_twice: HashSet<Uuid>,
+    pub guesses1: Vec<(Uuid, usize)>,
+    pub guesses2: Vec<(Uuid, usize)>,
+    pub last_guesses: Vec<(Uuid, usize)>,
+}
+
+#[derive(Debug)]
+struct Player { id: Uuid, name: String, score: i32, bullseyes: u32, ready: bool, user_id: Option<Uuid>, avatar: Option<String>, bot: bool }
+
+#[derive(Debug)]
+struct RoomState {
+    name: String,
+    round: u32,
+    board: board::Board,
+    cue_giver_idx: usize,
+    phase: Phase,
+    cue1: Option<String>,
+    cue2: Option<String>,
+    target: Option<usize>,
+    select_options: Option<Vec<usize>>,
+    cue_rules: CueRules,
+    scoring: Scoring,
+    round_result: Option<RoundResult>,
+    game_length: GameLength,
+    /// Seats taken when the current game started; rotations are counted for that table, whoever joins or leaves later.
+    seats_at_start: usize,
+    standings: Option<Vec<Standing>>,
+    limits: PlayerLimits,
+    players: Vec<Player>,
+    guessed_once: HashSet<Uuid>,
+    guessed_twice: HashSet<Uuid>,
+    guess1_cells: HashMap<Uuid, usize>,
+    guess2_cells: HashMap<Uuid, usize>,
+    /// Joinable only through an invite.
+    private: bool,
+    game: GameKind,
+    /// Feeds the rules' random draws (see `game::reduce`).
+    seed: u64,
+    /// When the current game started, in unix seconds; kept by the hub for match history, the rules ignore it.
+    started_at: Option<i64>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Phase { Lobby, Cue1, Guess1, Cue2, Guess2, Reveal, GameOver }
+
+impl Phase {
+    fn as_str(self) -> &'static str {
+        match self { Phase::Lobby=>"lobby", Phase::Cue1=>"cue1", Phase::Guess1=>"guess1", Phase::Cue2=>"cue2", Phase::Guess2=>"guess2", Phase::Reveal=>"reveal", Phase::GameOver=>"game_over" }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum GameKind { HuesAndCues, Stickers }
+
+impl GameKind {
+    fn as_str(self) -> &'static str {
+        match self { GameKind::HuesAndCues => "hues_and_cues", GameKind::Stickers => "stickers" }
+    }
+
+    /// Seats a room of this game may have, and how many it needs to start.
+    fn player_limits(self) -> PlayerLimits {
+        match self {
+            GameKind::HuesAndCues => PlayerLimits { min: 2, max: 10 },
+            GameKind::Stickers => PlayerLimits { min: 2, max: 8 },
+        }
+    }
+
+    /// The room name decides the game: `stickers` is Stickers, every other room Hues and Cues.
+    fn for_room(room: &str) -> Self {
+        if room == "stickers" { GameKind::Stickers } else { GameKind::HuesAndCues }
+    }
+}
+
+/// Rooms the bundled frontends join by fixed name; nobody may make them invite-only.
+const PUBLIC_ROOMS: &[&str] = &["default", "colors", "stickers"];
+
+#[derive(Default, Debug)]
+struct WsHub {
+    rooms: HashMap<String, RoomState>,
+    conns: HashMap<Uuid, (String, Uuid)>,
+    txs: HashMap<Uuid, tokio::sync::mpsc::UnboundedSender<Message>>, 
+    /// conn_id -> account, for connections that joined with a token.
+    conn_users: HashMap<Uuid, Uuid>,
+    /// Accounts with at least one open connection.
+    accounts: HashMap<Uuid, OnlineAccount>,
+    /// Presence last announced to each account's friends, so unchanged states are not re-sent.
+    presence: HashMap<Uuid, FriendPresence>,
+    invites: HashMap<Uuid, RoomInvite>,
+    /// Seats without a socket, keyed like `conns` by a connection id of their own.
+    bots: HashMap<Uuid, bots::Bot>,
+    /// Recent answers to numbered messages, by `reply_key`.
+    replies: HashMap<String, RecentReplies>,
+    /// Finished games go here to be written to `matches`; `None` keeps them out of the database.
+    match_log: Option<tokio::sync::mpsc::UnboundedSender<FinishedMatch>>,
+}
+
+/// A game that reached `game_over`, as it goes into match history.
+#[derive(Debug)]
+struct FinishedMatch { room: String, started_at: i64, ended_at: i64, players: Vec<MatchPlayer> }
+
+/// `place` as in the final standings, where ties on score are broken by bullseyes.
+#[derive(Debug)]
+struct MatchPlayer { user_id: Option<Uuid>, name: String, score: i32, place: u32 }
+
+#[derive(Debug)]
+struct OnlineAccount {
+    username: String,
+    avatar: Option<String>,
+    /// Accepted friends, used for presence and to flag players per viewer.
+    friends: HashSet<Uuid>,
+}
+
+#[derive(Debug)]
+struct RoomInvite { from: Uuid, to: Uuid, room: String, created: std::time::Instant }
+
+const INVITE_TTL: std::time::Duration = std::time::Duration::from_secs(300);
+
+type SharedHub = Arc<tokio::sync::Mutex<WsHub>>;
+
+fn default_room() -> RoomState {
+    RoomState {
+        name: "default".to_string(),
+        round: 0,
+        board: board::Board::CLASSIC,
+        cue_giver_idx: 0,
+        phase: Phase::Lobby,
+        cue1: None,
+        cue2: None,
+        target: None,
+        select_options: None,
+        cue_rules: CueRules::default(),
+        scoring: Scoring::default(),
+        round_result: None,
+        game_length: GameLength::default(),
+        seats_at_start: 0,
+        standings: None,
+        limits: GameKind::HuesAndCues.player_limits(),
+        players: vec![],
+        guessed_once: HashSet::new(),
+        guessed_twice: HashSet::new(),
+        guess1_cells: HashMap::new(),
+        guess2_cells: HashMap::new(),
+        private: false,
+        game: GameKind::HuesAndCues,
+        seed: rand::random(),
+        started_at: None,
+    }
+}
+
+// ===================== Global State =====================
+#[derive(Clone)]
+pub struct AppState {
+    cfg: AppConfig,
+    db: SqlitePool,
+    hub: SharedHub,
+    auth_limiter: Arc<AuthLimiter>,
+    /// The trusted legacy export (see [`load_legacy_accounts`]), keyed by normalised name.
+    legacy_accounts: Arc<HashMap<String, LegacyAccount>>,
+    started_at: Instant,
+}
+
+/// How often bots look at their rooms; their think time is on top of this.
+const BOT_TICK: Duration = Duration::from_millis(250);
+
+impl AppState {
+    /// Wraps an already migrated pool; starts the limiter cleanup task, so call it inside the runtime.
+    pub async fn new(cfg: AppConfig, db: SqlitePool) -> anyhow::Result<Self> {
+        tokio::fs::create_dir_all(avatar_dir(&cfg)).await?;
+        let legacy_accounts = Arc::new(load_legacy_accounts(&cfg).await?);
+
+        // WS hub
+        let hub: SharedHub = Arc::new(tokio::sync::Mutex::new(WsHub::default()));
+        let (match_tx, mut match_rx) = tokio::sync::mpsc::unbounded_channel();
+        {
+            let mut guard = hub.lock().await;
+            guard.rooms.insert("default".into(), default_room());
+            guard.match_log = Some(match_tx);
+        }
+        {
+            let db = db.clone();
+            tokio::spawn(async move {
+                while let Some(finished) = match_rx.recv().await {
+                    if let Err(e) = record_match(&db, finished).await {
+                        tracing::error!(target: "keldurben_server", event="record_match_failed", error=%e);
+                    }
+                }
+            });
+        }
+
+        let auth_limiter = Arc::new(AuthLimiter::default());
+        {
+            let limiter = auth_limiter.clone();
+            tokio::spawn(async move {
+                let mut tick = tokio::time::interval(Duration::from_secs(60));
+                loop { tick.tick().await; limiter.prune(); }
+            });
+        }
+        {
+            let hub = hub.clone();
+            tokio::spawn(async move {
+                let mut tick = tokio::time::interval(BOT_TICK);
+                loop {
+                    tick.tick().await;
+                    let mut hub = hub.lock().await;
+                    if !hub.bots.is_empty() { drive_bots(&mut hub); }
+                }
+            });
+        }
+
+        Ok(Self { cfg, db, hub, auth_limiter, legacy_accounts, started_at: Instant::now() })
+    }
+}
+
+// ===================== Router =====================
+/// The public app. `/metrics` is included unless `metrics_bind` moves it to [`metrics_router`].
+/// Serve it with `into_make_service_with_connect_info::<SocketAddr>()`: auth rate limiting needs the peer address.
+pub fn build_router(state: AppState) -> Router {
+    let cfg = &state.cfg;
+    let cors = CorsLayer::new()
+        .allow_origin(HeaderValue::from_static("*"))
+        .allow_methods([Method::GET, Method::POST, Method::PUT, Method::PATCH, Method::DELETE, Method::OPTIONS])
+        .allow_headers([axum::http::header::CONTENT_TYPE, axum::http::header::AUTHORIZATION]);
+
+    let static_dir = cfg.static_dir.clone();
+    let mut app = Router::new()
+        .route("/ws", get(ws_handler))
+        .route("/api/auth/register", post(register).layer(axum::middleware::from_fn(count_auth_attempt)))
+        .route("/api/auth/login", post(login).layer(axum::middleware::from_fn(count_auth_attempt)))
+        .route("/api/auth/import-legacy", post(import_legacy).layer(axum::middleware::from_fn(count_auth_attempt)))
+        .route("/api/me", get(me).patch(update_profile).delete(delete_account))
+        .route("/api/me/password", put(change_password))
+        .route("/api/me/username", put(change_username))
+        .route("/api/me/settings", get(get_settings).put(put_settings))
+        .route("/api/me/avatar", put(upload_avatar).layer(DefaultBodyLimit::max(MAX_AVATAR_UPLOAD)))
+        .route("/api/avatars/:file", get(generated_avatar))
+        .route("/api/users/:id", get(user_profile))
+        .route("/api/boards/:id", get(board_palette))
+        .route("/api/friends", get(list_friends))
+        .route("/api/friends/requests", post(send_friend_request))
+        .route("/api/friends/requests/:id/accept", post(accept_friend_request))
+        .route("/api/friends/requests/:id/decline", post(decline_friend_request))
+        .route("/api/friends/:id", delete(remove_friend))
+        .route("/api/friends/:id/block", post(block_user).delete(unblock_user))
+        .nest_service("/media/avatars", {
+            // Upload file names are never reused, so clients may cache them forever.
+            tower::ServiceBuilder::new()
+                .layer(SetResponseHeaderLayer::overriding(header::CACHE_CONTROL, HeaderValue::from_static("public, max-age=31536000, immutable")))
+                .service(ServeDir::new(avatar_dir(cfg)))
+        })
+        .route("/healthz", get(healthz))
+        .route("/readyz", get(readyz))
+        .route("/api/debug/state", get(debug_state))
+        .route("/api/admin/reset", post(admin_reset))
+        .route("/api/admin/kick", post(admin_kick));
+    if cfg.metrics_bind.is_none() {
+        app = app.route("/metrics", get(metrics));
+    }
+    app
+        .fallback_service({
+            let file_service = ServeDir::new(static_dir.clone())
+                .append_index_html_on_directories(true)
+                .fallback(ServeFile::new(format!("{}/index.html", static_dir)));
+            axum::routing::get_service(file_service)
+        })
+        .layer(cors)
+        .layer(CompressionLayer::new())
+        .layer(TraceLayer::new_for_http())
+        .with_state(state)
+}
+
+/// `/metrics` alone, for the separate `METRICS_BIND` listener.
+pub fn metrics_router(state: AppState) -> Router {
+    Router::new().route("/metrics", get(metrics)).with_state(state)
+}
+
+// ===================== REST: Auth =====================
+#[derive(Deserialize)]
+struct Claims {
+    sub: String,
+    #[allow(dead_code)]
+    exp: usize,
+}
+
+async fn register(State(app): State<AppState>, ConnectInfo(peer): ConnectInfo<SocketAddr>, headers: HeaderMap, Json(payload): Json<AuthPayload>) -> impl IntoResponse {
+    // Only refused signups count: several people behind one address may well register in a row.
+    let ip_key = format!("register-ip:{}", client_ip(&app.cfg, peer, &headers));
+    let username = payload.username.trim().to_string();
+    let user_key = format!("register-user:{}", normalize_username(&username));
+    if let Err(wait) = app.auth_limiter.attempt(&[&ip_key, &user_key]) {
+        return too_many_attempts(wait);
+    }
+    if let Err(e) = validate_username(&username) {
+        return (StatusCode::BAD_REQUEST, Json(serde_json::json!({"error": e}))).into_response();
+    }
+    if payload.password.len() < 4 {
+        return (StatusCode::BAD_REQUEST, Json(serde_json::json!({"error":"password_too_short"}))).into_response();
+    }
+    let id = Uuid::new_v4();
+    let hash = match hash_password_async(&app.cfg.argon2_params, &payload.password).await {
+        Ok(hash) => hash,
+        Err(e) => {
+            tracing::error!(target: "keldurben_server", event="hash_failed", error=%e);
+            return (StatusCode::INTERNAL_SERVER_ERROR, Json(serde_json::json!({"error":"hash_error"}))).into_response();
+        }
+    };
+    let res = timed("register_user", sqlx::query("INSERT INTO users (id, username, username_norm, pwd_hash, avatar) VALUES (?1, ?2, ?3, ?4, ?5)")
+        .bind(id.to_string())
+        .bind(&username)
+        .bind(normalize_username(&username))
+        .bind(hash)
+        .bind(Option::<String>::None)
+        .execute(&app.db)).await;
+    if let Err(e) = res {
+        if e.to_string().contains("UNIQUE") {
+            return (StatusCode::BAD_REQUEST, Json(serde_json::json!({"error":"username_taken"}))).into_response();
+        }
+        tracing::error!(target: "keldurben_server", event="db_error", error=%e);
+        return (StatusCode::INTERNAL_SERVER_ERROR, Json(serde_json::json!({"error":"db_error"}))).into_response();
+    }
+    app.auth_limiter.refund(&[&ip_key]);
+    app.auth_limiter.reset(&user_key);
+    let token = issue_jwt(&app.cfg.jwt_secret, id);
+    let user = PublicUser::new(id, username, None);
+    (StatusCode::OK, Json(AuthResponse { token, user })).into_response()
+}
+
+async fn login(State(app): State<AppState>, ConnectInfo(peer): ConnectInfo<SocketAddr>, headers: HeaderMap, Json(payload): Json<AuthPayload>) -> impl IntoResponse {
+    let ip_key = format!("login-ip:{}", client_ip(&app.cfg, peer, &headers));
+    let user_key = format!("login-user:{}", normalize_username(payload.username.trim()));
+    if let Err(wait) = app.auth_limiter.attempt(&[&ip_key, &user_key]) {
+        return too_many_attempts(wait);
+    }
+    let row_res = find_user_by_name(&app.db, &payload.username).await;
+    let row = match row_res {
+        Ok(Some(row)) => row,
+        _ => return (StatusCode::UNAUTHORIZED, Json(serde_json::json!({"error":"invalid_credentials"}))).into_response(),
+    };
+    if !verify_password_async(&payload.password, &row.pwd_hash).await {
+        return (StatusCode::UNAUTHORIZED, Json(serde_json::json!({"error":"invalid_credentials"}))).into_response();
+    }
+    app.auth_limiter.refund(&[&ip_key]);
+    app.auth_limiter.reset(&user_key);
+    if needs_rehash(&app.cfg.argon2_params, &row.pwd_hash) {
+        // Legacy SHA-256 or weaker argon2 than current policy: we have the plain password now, so upgrade.
+        // A failure here is logged and the login still goes through on the old hash.
+        let res = match hash_password_async(&app.cfg.argon2_params, &payload.password).await {
+            Ok(hash) => sqlx::query("UPDATE users SET pwd_hash = ?1 WHERE id = ?2 AND pwd_hash = ?3")
+                .bind(hash)
+                .bind(&row.id)
+                .bind(&row.pwd_hash)
+                .execute(&app.db).await
+                .map(|_| ())
+                .map_err(anyhow::Error::from),
+            Err(e) => Err(anyhow::anyhow!(e)),
+        };
+        match res {
+            Ok(()) => tracing::info!(target: "keldurben_server", event="password_rehash", user_id=%row.id),
+            Err(e) => tracing::warn!(target: "keldurben_server", event="password_rehash_failed", user_id=%row.id, error=%e),
+        }
+    }
+    let id = Uuid::parse_str(&row.id).unwrap_or_else(|_| Uuid::nil());
+    let token = issue_jwt(&app.cfg.jwt_secret, id);
+    let user = PublicUser::new(id, row.username, row.avatar);
+    (StatusCode::OK, Json(AuthResponse { token, user })).into_response()
+}
+
+/// Where operators put the `localStorage('accounts')` lists collected from the old client:
+/// a JSON array of `{ username, passwordHash }`, the only source legacy imports are checked against.
+fn legacy_accounts_path(cfg: &AppConfig) -> std::path::PathBuf {
+    std::path::Path::new(&cfg.data_dir).join("legacy-accounts.json")
+}
+
+/// Reads the legacy export; without the file nothing can be imported. Entries with a malformed hash are skipped,
+/// and when two entries share a normalised name the first one wins.
+async fn load_legacy_accounts(cfg: &AppConfig) -> anyhow::Result<HashMap<String, LegacyAccount>> {
+    let path = legacy_accounts_path(cfg);
+    let text = match tokio::fs::read_to_string(&path).await {
+        Ok(text) => text,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(HashMap::new()),
+        Err(e) => return Err(anyhow::anyhow!("{}: {}", path.display(), e)),
+    };
+    let list: Vec<LegacyAccount> = serde_json::from_str(&text).map_err(|e| anyhow::anyhow!("{}: {}", path.display(), e))?;
+    let mut accounts = HashMap::new();
+    for mut account in list {
+        account.password_hash = account.password_hash.trim().to_ascii_lowercase();
+        if account.password_hash.len() != 64 || !account.password_hash.bytes().all(|b| b.is_ascii_hexdigit()) {
+            tracing::warn!(target: "keldurben_server", event="legacy_account_skipped", username=%account.username);
+            continue;
+        }
+        accounts.entry(normalize_username(account.username.trim())).or_insert(account);
+    }
+    info!("loaded {} legacy accounts", accounts.len());
+    Ok(accounts)
+}
+
+/// Brings over one account from the old `localStorage` store. The caller proves ownership with
+/// the legacy password, which must match the hash in the operator's export.
+///
+/// Without a token a server account is created under the legacy name, keeping the
+/// SHA-256 hash (flagged) until the first successful login replaces it. With a
+/// token the legacy account is linked to the caller instead, reserving it so it
+/// cannot be imported by anyone else.
+async fn import_legacy(
+    State(app): State<AppState>,
+    ConnectInfo(peer): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
+    auth: Option<AuthBearer>,
+    Json(payload): Json<LegacyImportPayload>,
+) -> impl IntoResponse {
+    let ip_key = format!("register-ip:{}", client_ip(&app.cfg, peer, &headers));
+    let legacy_norm = normalize_username(payload.username.trim());
+    let user_key = format!("import-user:{}", legacy_norm);
+    if let Err(wait) = app.auth_limiter.attempt(&[&ip_key, &user_key]) {
+        return too_many_attempts(wait);
+    }
+
+    let legacy = match app.legacy_accounts.get(&legacy_norm) {
+        Some(l) if verify_password_async(&payload.password, &format!("{}{}", LEGACY_HASH_PREFIX, l.password_hash)).await => l,
+        _ => return (StatusCode::UNAUTHORIZED, Json(serde_json::json!({"error":"invalid_credentials"}))).into_response(),
+    };
+    app.auth_limiter.refund(&[&ip_key]);
+    app.auth_limiter.reset(&user_key);
+    let username = legacy.username.trim().to_string();
+    let now = time::OffsetDateTime::now_utc().unix_timestamp();
+
+    if let Some(auth) = auth {
+        let user = match auth_user(&app, &auth.0).await {
+            Ok(user) => user,
+            Err(_) => return (StatusCode::UNAUTHORIZED, Json(serde_json::json!({"error":"unauthorized"}))).into_response(),
+        };
+        let res = sqlx::query("INSERT INTO legacy_imports (legacy_username_norm, legacy_username, user_id, imported_at) VALUES (?1, ?2, ?3, ?4)")
+            .bind(&legacy_norm)
+            .bind(&username)
+            .bind(user.id.to_string())
+            .bind(now)
+            .execute(&app.db).await;
+        return match res {
+            Ok(_) => (StatusCode::OK, Json(serde_json::json!({"status":"linked", "user": user}))).into_response(),
+            Err(e) if e.to_string().contains("UNIQUE") => (StatusCode::CONFLICT, Json(serde_json::json!({"error":"already_imported"}))).into_response(),
+            Err(_) => (StatusCode::INTERNAL_SERVER_ERROR, Json(serde_json::json!({"error":"db_error"}))).into_response(),
+        };
+    }
+
+    if let Err(e) = validate_username(&username) {
+        return (StatusCode::BAD_REQUEST, Json(serde_json::json!({"error": e}))).into_response();
+    }
+    let id = Uuid::new_v4();
+    let res: Result<(), sqlx::Error> = async {
+        let mut tx = app.db.begin().await?;
+        sqlx::query("INSERT INTO users (id, username, username_norm, pwd_hash, avatar) VALUES (?1, ?2, ?3, ?4, NULL)")
+            .bind(id.to_string())
+            .bind(&username)
+            .bind(&legacy_norm)
+            .bind(format!("{}{}", LEGACY_HASH_PREFIX, legacy.password_hash))
+            .execute(&mut *tx).await?;
+        sqlx::query("INSERT INTO legacy_imports (legacy_username_norm, legacy_username, user_id, imported_at) VALUES (?1, ?2, ?3, ?4)")
+            .bind(&legacy_norm)
+            .bind(&username)
+            .bind(id.to_string())
+            .bind(now)
+            .execute(&mut *tx).await?;
+        tx.commit().await
+    }.await;
+    match res {
+        Ok(()) => {
+            tracing::info!(target: "keldurben_server", event="legacy_import", user_id=%id);
+            let user = PublicUser::new(id, username, None);
+            (StatusCode::CREATED, Json(serde_json::json!({"status":"created", "user": user}))).into_response()
+        }
+        Err(e) if e.to_string().contains("legacy_imports") => (StatusCode::CONFLICT, Json(serde_json::json!({"error":"already_imported"}))).into_response(),
+        Err(e) if e.to_string().contains("UNIQUE") => (StatusCode::CONFLICT, Json(serde_json::json!({"error":"username_taken"}))).into_response(),
+        Err(_) => (StatusCode::INTERNAL_SERVER_ERROR, Json(serde_json::json!({"error":"db_error"}))).into_response(),
+    }
+}
+
+async fn me(State(app): State<AppState>, auth: AuthBearer) -> impl IntoResponse {
+    match auth_user(&app, &auth.0).await {
+        Ok(user) => (StatusCode::OK, Json(user)).into_response(),
+        Err(_) => (StatusCode::UNAUTHORIZED, Json(serde_json::json!({"error":"unauthorized"}))).into_response(),
+    }
+}
+
+// ===================== REST: Profile =====================
+async fn update_profile(State(app): State<AppState>, auth: AuthBearer, Json(payload): Json<UpdateProfilePayload>) -> impl IntoResponse {
+    let user = match auth_user(&app, &auth.0).await {
+        Ok(user) => user,
+        Err(_) => return (StatusCode::UNAUTHORIZED, Json(serde_json::json!({"error":"unauthorized"}))).into_response(),
+    };
+    let display_name = payload.display_name.map(|v| v.trim().to_string());
+    let bio = payload.bio.map(|v| v.trim().to_string());
+    if display_name.as_ref().is_some_and(|v| v.chars().count() > MAX_DISPLAY_NAME_CHARS) {
+        return (StatusCode::BAD_REQUEST, Json(serde_json::json!({"error":"display_name_too_long"}))).into_response();
+    }
+    if bio.as_ref().is_some_and(|v| v.chars().count() > MAX_BIO_CHARS) {
+        return (StatusCode::BAD_REQUEST, Json(serde_json::json!({"error":"bio_too_long"}))).into_response();
+    }
+    let res = sqlx::query(
+        "UPDATE users SET display_name = CASE WHEN ?1 IS NULL THEN display_name ELSE NULLIF(?1, '') END, \
+                          bio = CASE WHEN ?2 IS NULL THEN bio ELSE NULLIF(?2, '') END \
+         WHERE id = ?3")
+        .bind(display_name)
+        .bind(bio)
+        .bind(user.id.to_string())
+        .execute(&app.db).await;
+    if res.is_err() {
+        return (StatusCode::INTERNAL_SERVER_ERROR, Json(serde_json::json!({"error":"db_error"}))).into_response();
+    }
+    match load_profile(&app.db, user.id).await {
+        Ok(Some(profile)) => (StatusCode::OK, Json(profile)).into_response(),
+        _ => (StatusCode::INTERNAL_SERVER_ERROR, Json(serde_json::json!({"error":"db_error"}))).into_response(),
+    }
+}
+
+async fn user_profile(State(app): State<AppState>, Path(id): Path<Uuid>) -> impl IntoResponse {
+    match load_profile(&app.db, id).await {
+        Ok(Some(profile)) => (StatusCode::OK, Json(profile)).into_response(),
+        Ok(None) => (StatusCode::NOT_FOUND, Json(serde_json::json!({"error":"not_found"}))).into_response(),
+        Err(_) => (StatusCode::INTERNAL_SERVER_ERROR, Json(serde_json::json!({"error":"db_error"}))).into_response(),
+    }
+}
+
+/// Games played counts finished matches only; a win is finishing first in the standings (shared places included).
+async fn load_profile(db: &SqlitePool, id: Uuid) -> anyhow::Result<Option<PublicProfile>> {
+    let profile = sqlx::query_as::<_, PublicProfile>(
+        r#"
+        SELECT u.id, u.username, u.display_name, u.bio, u.avatar,
+            (SELECT COUNT(*) FROM match_players mp JOIN matches m ON m.id = mp.match_id
+                WHERE mp.user_id = u.id AND m.ended_at IS NOT NULL) AS games_played,
+            (SELECT COUNT(*) FROM match_players mp JOIN matches m ON m.id = mp.match_id
+                WHERE mp.user_id = u.id AND m.ended_at IS NOT NULL
+                AND mp.place = 1) AS wins
+        FROM users u WHERE u.id = ?1
+        "#)
+        .bind(id.to_string());
+    let profile = timed("load_profile", profile.fetch_optional(db)).await?;
+    Ok(profile.map(|p| PublicProfile { avatar: p.avatar.or_else(|| Some(default_avatar_url(&p.username))), ..p }))
+}
+
+// ===================== REST: Settings =====================
+async fn get_settings(State(app): State<AppState>, auth: AuthBearer) -> impl IntoResponse {
+    let user = match auth_user(&app, &auth.0).await {
+        Ok(user) => user,
+        Err(_) => return (StatusCode::UNAUTHORIZED, Json(serde_json::json!({"error":"unauthorized"}))).into_response(),
+    };
+    match load_settings(&app.db, user.id).await {
+        Ok(settings) => (StatusCode::OK, Json(settings)).into_response(),
+        Err(_) => (StatusCode::INTERNAL_SERVER_ERROR, Json(serde_json::json!({"error":"db_error"}))).into_response(),
+    }
+}
+
+async fn put_settings(State(app): State<AppState>, auth: AuthBearer, Json(settings): Json<UserSettings>) -> impl IntoResponse {
+    let user = match auth_user(&app, &auth.0).await {
+        Ok(user) => user,
+        Err(_) => return (StatusCode::UNAUTHORIZED, Json(serde_json::json!({"error":"unauthorized"}))).into_response(),
+    };
+    if let Err(e) = settings.validate() {
+        return (StatusCode::BAD_REQUEST, Json(serde_json::json!({"error": e}))).into_response();
+    }
+    let settings = settings.upgrade();
+    let data = serde_json::to_string(&settings).unwrap_or_default();
+    let res = sqlx::query(
+        "INSERT INTO user_settings (user_id, version, data, updated_at) VALUES (?1, ?2, ?3, ?4) \
+         ON CONFLICT(user_id) DO UPDATE SET version = excluded.version, data = excluded.data, updated_at = excluded.updated_at")
+        .bind(user.id.to_string())
+        .bind(settings.version)
+        .bind(data)
+        .bind(time::OffsetDateTime::now_utc().unix_timestamp())
+        .execute(&app.db).await;
+    if res.is_err() {
+        return (StatusCode::INTERNAL_SERVER_ERROR, Json(serde_json::json!({"error":"db_error"}))).into_response();
+    }
+    (StatusCode::OK, Json(settings)).into_response()
+}
+
+/// Missing keys come back as defaults; a document that no longer parses is reset rather than failing the request.
+async fn load_settings(db: &SqlitePool, user_id: Uuid) -> anyhow::Result<UserSettings> {
+    let row: Option<(String,)> = timed("load_settings", sqlx::query_as("SELECT data FROM user_settings WHERE user_id = ?1")
+        .bind(user_id.to_string())
+        .fetch_optional(db)).await?;
+    let Some((data,)) = row else { return Ok(UserSettings::default()) };
+    match serde_json::from_str::<UserSettings>(&data) {
+        Ok(settings) => Ok(settings.upgrade()),
+        Err(e) => {
+            tracing::warn!(target: "keldurben_server", event="settings_unreadable", user_id=%user_id, error=%e);
+            Ok(UserSettings::default())
+        }
+    }
+}
+
+// ===================== REST: Friends =====================
+async fn list_friends(State(app): State<AppState>, auth: AuthBearer) -> impl IntoResponse {
+    let user = match auth_user(&app, &auth.0).await {
+        Ok(user) => user,
+        Err(_) => return (StatusCode::UNAUTHORIZED, Json(serde_json::json!({"error":"unauthorized"}))).into_response(),
+    };
+    let me = user.id.to_string();
+    let fetch = |sql: &'static str| {
+        let db = app.db.clone();
+        let me = me.clone();
+        async move { sqlx::query_as::<_, FriendEntry>(sql).bind(me).fetch_all(&db).await }
+    };
+    let lists = tokio::try_join!(
+        fetch("SELECT u.id, u.username, u.avatar, f.created_at AS since FROM friendships f JOIN users u ON u.id = CASE WHEN f.user_id = ?1 THEN f.friend_id ELSE f.user_id END \
+               WHERE (f.user_id = ?1 OR f.friend_id = ?1) AND f.status = 'accepted' ORDER BY u.username"),
+        fetch("SELECT u.id, u.username, u.avatar, f.created_at AS since FROM friendships f JOIN users u ON u.id = f.user_id \
+               WHERE f.friend_id = ?1 AND f.status = 'pending' ORDER BY f.created_at"),
+        fetch("SELECT u.id, u.username, u.avatar, f.created_at AS since FROM friendships f JOIN users u ON u.id = f.friend_id \
+               WHERE f.user_id = ?1 AND f.status = 'pending' ORDER BY f.created_at"),
+        fetch("SELECT u.id, u.username, u.avatar, f.created_at AS since FROM friendships f JOIN users u ON u.id = f.friend_id \
+               WHERE f.user_id = ?1 AND f.status = 'blocked' ORDER BY u.username"),
+    );
+    let Ok((friends, incoming, outgoing, blocked)) = lists else {
+        return (StatusCode::INTERNAL_SERVER_ERROR, Json(serde_json::json!({"error":"db_error"}))).into_response();
+    };
+    let with_avatars = |list: Vec<FriendEntry>| -> Vec<FriendEntry> {
+        list.into_iter().map(|f| FriendEntry { avatar: f.avatar.or_else(|| Some(default_avatar_url(&f.username))), ..f }).collect()
+    };
+    let body = FriendsResponse {
+        friends: with_avatars(friends),
+        incoming: with_avatars(incoming),
+        outgoing: with_avatars(outgoing),
+        blocked: with_avatars(blocked),
+    };
+    (StatusCode::OK, Json(body)).into_response()
+}
+
+/// Sends a request by username. A pending request in the other direction is accepted instead.
+async fn send_friend_request(State(app): State<AppState>, auth: AuthBearer, Json(payload): Json<FriendRequestPayload>) -> impl IntoResponse {
+    let user = match auth_user(&app, &auth.0).await {
+        Ok(user) => user,
+        Err(_) => return (StatusCode::UNAUTHORIZED, Json(serde_json::json!({"error":"unauthorized"}))).into_response(),
+    };
+    let target = match find_user_by_name(&app.db, &payload.username).await {
+        Ok(row) => row,
+        Err(_) => return (StatusCode::INTERNAL_SERVER_ERROR, Json(serde_json::json!({"error":"db_error"}))).into_response(),
+    };
+    let Some(target) = target.and_then(|row| Uuid::parse_str(&row.id).ok()) else {
+        return (StatusCode::NOT_FOUND, Json(serde_json::json!({"error":"not_found"}))).into_response();
+    };
+    if target == user.id {
+        return (StatusCode::BAD_REQUEST, Json(serde_json::json!({"error":"cannot_friend_self"}))).into_response();
+    }
+    let rows = match friendship_rows(&app.db, user.id, target).await {
+        Ok(rows) => rows,
+        Err(_) => return (StatusCode::INTERNAL_SERVER_ERROR, Json(serde_json::json!({"error":"db_error"}))).into_response(),
+    };
+    let outgoing = rows.iter().find(|(from, _)| *from == user.id).map(|(_, s)| s.as_str());
+    let incoming = rows.iter().find(|(from, _)| *from == target).map(|(_, s)| s.as_str());
+    let res = match (outgoing, incoming) {
+        (Some("blocked"), _) | (_, Some("blocked")) => {
+            return (StatusCode::FORBIDDEN, Json(serde_json::json!({"error":"blocked"}))).into_response();
+        }
+        (Some("accepted"), _) | (_, Some("accepted")) => {
+            return (StatusCode::CONFLICT, Json(serde_json::json!({"error":"already_friends"}))).into_response();
+        }
+        (Some("pending"), _) => {
+            return (StatusCode::CONFLICT, Json(serde_json::json!({"error":"already_requested"}))).into_response();
+        }
+        (_, Some("pending")) => sqlx::query("UPDATE friendships SET status = 'accepted' WHERE user_id = ?1 AND friend_id = ?2")
+            .bind(target.to_string())
+            .bind(user.id.to_string())
+            .execute(&app.db).await
+            .map(|_| "accepted"),
+        _ => sqlx::query("INSERT INTO friendships (user_id, friend_id, status, created_at) VALUES (?1, ?2, 'pending', ?3)")
+            .bind(user.id.to_string())
+            .bind(target.to_string())
+            .bind(time::OffsetDateTime::now_utc().unix_timestamp())
+            .execute(&app.db).await
+            .map(|_| "pending"),
+    };
+    match res {
+        Ok(status) => {
+            if status == "accepted" { refresh_friends(&app, &[user.id, target]).await; }
+            (StatusCode::OK, Json(serde_json::json!({"status": status}))).into_response()
+        }
+        Err(_) => (StatusCode::INTERNAL_SERVER_ERROR, Json(serde_json::json!({"error":"db_error"}))).into_response(),
+    }
+}
+
+async fn accept_friend_request(State(app): State<AppState>, auth: AuthBearer, Path(from): Path<Uuid>) -> impl IntoResponse {
+    let user = match auth_user(&app, &auth.0).await {
+        Ok(user) => user,
+        Err(_) => return (StatusCode::UNAUTHORIZED, Json(serde_json::json!({"error":"unauthorized"}))).into_response(),
+    };
+    let res = sqlx::query("UPDATE friendships SET status = 'accepted' WHERE user_id = ?1 AND friend_id = ?2 AND status = 'pending'")
+        .bind(from.to_string())
+        .bind(user.id.to_string())
+        .execute(&app.db).await;
+    match res {
+        Ok(r) if r.rows_affected() == 0 => (StatusCode::NOT_FOUND, Json(serde_json::json!({"error":"no_request"}))).into_response(),
+        Ok(_) => {
+            refresh_friends(&app, &[user.id, from]).await;
+            StatusCode::NO_CONTENT.into_response()
+        }
+        Err(_) => (StatusCode::INTERNAL_SERVER_ERROR, Json(serde_json::json!({"error":"db_error"}))).into_response(),
+    }
+}
+
+async fn decline_friend_request(State(app): State<AppState>, auth: AuthBearer, Path(from): Path<Uuid>) -> impl IntoResponse {
+    let user = match auth_user(&app, &auth.0).await {
+        Ok(user) => user,
+        Err(_) => return (StatusCode::UNAUTHORIZED, Json(serde_json::json!({"error":"unauthorized"}))).into_response(),
+    };
+    let res = sqlx::query("DELETE FROM friendships WHERE user_id = ?1 AND friend_id = ?2 AND status = 'pending'")
+        .bind(from.to_string())
+        .bind(user.id.to_string())
+        .execute(&app.db).await;
+    match res {
+        Ok(r) if r.rows_affected() == 0 => (StatusCode::NOT_FOUND, Json(serde_json::json!({"error":"no_request"}))).into_response(),
+        Ok(_) => StatusCode::NO_CONTENT.into_response(),
+        Err(_) => (StatusCode::INTERNAL_SERVER_ERROR, Json(serde_json::json!({"error":"db_error"}))).into_response(),
+    }
+}
+
+/// Ends a friendship or withdraws our own pending request. Blocks are left alone.
+async fn remove_friend(State(app): State<AppState>, auth: AuthBearer, Path(other): Path<Uuid>) -> impl IntoResponse {
+    let user = match auth_user(&app, &auth.0).await {
+        Ok(user) => user,
+        Err(_) => return (StatusCode::UNAUTHORIZED, Json(serde_json::json!({"error":"unauthorized"}))).into_response(),
+    };
+    let res = sqlx::query(
+        "DELETE FROM friendships WHERE ((user_id = ?1 AND friend_id = ?2 AND status IN ('pending', 'accepted')) \
+                                     OR (user_id = ?2 AND friend_id = ?1 AND status = 'accepted'))")
+        .bind(user.id.to_string())
+        .bind(other.to_string())
+        .execute(&app.db).await;
+    match res {
+        Ok(r) if r.rows_affected() == 0 => (StatusCode::NOT_FOUND, Json(serde_json::json!({"error":"not_found"}))).into_response(),
+        Ok(_) => {
+            refresh_friends(&app, &[user.id, other]).await;
+            StatusCode::NO_CONTENT.into_response()
+        }
+        Err(_) => (StatusCode::INTERNAL_SERVER_ERROR, Json(serde_json::json!({"error":"db_error"}))).into_response(),
+    }
+}
+
+/// Blocking drops any friendship or request between the two and stops new requests both ways.
+async fn block_user(State(app): State<AppState>, auth: AuthBearer, Path(other): Path<Uuid>) -> impl IntoResponse {
+    let user = match auth_user(&app, &auth.0).await {
+        Ok(user) => user,
+        Err(_) => return (StatusCode::UNAUTHORIZED, Json(serde_json::json!({"error":"unauthorized"}))).into_response(),
+    };
+    if other == user.id {
+        return (StatusCode::BAD_REQUEST, Json(serde_json::json!({"error":"cannot_block_self"}))).into_response();
+    }
+    let res: anyhow::Result<()> = async {
+        let mut tx = app.db.begin().await?;
+        sqlx::query("DELETE FROM friendships WHERE (user_id = ?1 AND friend_id = ?2) OR (user_id = ?2 AND friend_id = ?1 AND status != 'blocked')")
+            .bind(user.id.to_string())
+            .bind(other.to_string())
+            .execute(&mut *tx).await?;
+        sqlx::query("INSERT INTO friendships (user_id, friend_id, status, created_at) VALUES (?1, ?2, 'blocked', ?3)")
+            .bind(user.id.to_string())
+            .bind(other.to_string())
+            .bind(time::OffsetDateTime::now_utc().unix_timestamp())
+            .execute(&mut *tx).await?;
+        tx.commit().await?;
+        Ok(())
+    }.await;
+    match res {
+        Ok(()) => {
+            refresh_friends(&app, &[user.id, other]).await;
+            StatusCode::NO_CONTENT.into_response()
+        }
+        // The FK rejects ids that are not accounts.
+        Err(e) if e.to_string().contains("FOREIGN KEY") => (StatusCode::NOT_FOUND, Json(serde_json::json!({"error":"not_found"}))).into_response(),
+        Err(e) => {
+            tracing::error!(target: "keldurben_server", event="db_error", error=%e);
+            (StatusCode::INTERNAL_SERVER_ERROR, Json(serde_json::json!({"error":"db_error"}))).into_response()
+        }
+    }
+}
+
+async fn unblock_user(State(app): State<AppState>, auth: AuthBearer, Path(other): Path<Uuid>) -> impl IntoResponse {
+    let user = match auth_user(&app, &auth.0).await {
+        Ok(user) => user,
+        Err(_) => return (StatusCode::UNAUTHORIZED, Json(serde_json::json!({"error":"unauthorized"}))).into_response(),
+    };
+    let res = sqlx::query("DELETE FROM friendships WHERE user_id = ?1 AND friend_id = ?2 AND status = 'blocked'")
+        .bind(user.id.to_string())
+        .bind(other.to_string())
+        .execute(&app.db).await;
+    match res {
+        Ok(r) if r.rows_affected() == 0 => (StatusCode::NOT_FOUND, Json(serde_json::json!({"error":"not_found"}))).into_response(),
+        Ok(_) => StatusCode::NO_CONTENT.into_response(),
+        Err(_) => (StatusCode::INTERNAL_SERVER_ERROR, Json(serde_json::json!({"error":"db_error"}))).into_response(),
+    }
+}
+
+/// `(requester, status)` for every row between the two accounts, in either direction.
+async fn friendship_rows(db: &SqlitePool, a: Uuid, b: Uuid) -> anyhow::Result<Vec<(Uuid, String)>> {
+    let rows: Vec<(String, String)> = sqlx::query_as(
+        "SELECT user_id, status FROM friendships WHERE (user_id = ?1 AND friend_id = ?2) OR (user_id = ?2 AND friend_id = ?1)")
+        .bind(a.to_string())
+        .bind(b.to_string())
+        .fetch_all(db).await?;
+    Ok(rows.into_iter().filter_map(|(id, status)| Uuid::parse_str(&id).ok().map(|id| (id, status))).collect())
+}
+
+async fn friend_ids(db: &SqlitePool, user_id: Uuid) -> anyhow::Result<HashSet<Uuid>> {
+    let rows: Vec<(String,)> = timed("friend_ids", sqlx::query_as(
+        "SELECT friend_id FROM friendships WHERE user_id = ?1 AND status = 'accepted' \
+         UNION SELECT user_id FROM friendships WHERE friend_id = ?1 AND status = 'accepted'")
+        .bind(user_id.to_string())
+        .fetch_all(db)).await?;
+    Ok(rows.into_iter().filter_map(|(id,)| Uuid::parse_str(&id).ok()).collect())
+}
+
+/// Reloads the cached friend sets of connected accounts and re-sends state to their rooms.
+async fn refresh_friends(app: &AppState, users: &[Uuid]) {
+    let mut fresh = Vec::new();
+    for &uid in users {
+        if let Ok(ids) = friend_ids(&app.db, uid).await { fresh.push((uid, ids)); }
+    }
+    let mut hub = app.hub.lock().await;
+    let mut rooms = HashSet::new();
+    for (uid, ids) in fresh {
+        let Some(account) = hub.accounts.get_mut(&uid) else { continue };
+        account.friends = ids;
+        let snapshot = ServerMsg::Presence { friends: presence_snapshot(&hub, uid) };
+        send_to_user(&hub, uid, &snapshot);
+        for (cid, u) in hub.conn_users.iter() {
+            if *u == uid {
+                if let Some((room, _)) = hub.conns.get(cid) { rooms.insert(room.clone()); }
+            }
+        }
+    }
+    for room in rooms { broadcast_state(room, &mut hub); }
+}
+
+// ===================== REST: Account =====================
+/// Checks the signed-in user's current password before a sensitive change; the refusal to answer with, if any.
+/// Failures count like failed logins, under the same per-username key, so a stolen token cannot be used to guess the password either.
+async fn recheck_password(app: &AppState, ip: IpAddr, row: &UserRow, password: &str) -> Option<axum::response::Response> {
+    let ip_key = format!("login-ip:{}", ip);
+    let user_key = format!("login-user:{}", normalize_username(&row.username));
+    if let Err(wait) = app.auth_limiter.attempt(&[&ip_key, &user_key]) {
+        return Some(too_many_attempts(wait));
+    }
+    if !verify_password_async(password, &row.pwd_hash).await {
+        return Some((StatusCode::FORBIDDEN, Json(serde_json::json!({"error":"invalid_credentials"}))).into_response());
+    }
+    app.auth_limiter.refund(&[&ip_key]);
+    app.auth_limiter.reset(&user_key);
+    None
+}
+
+async fn change_password(
+    State(app): State<AppState>,
+    ConnectInfo(peer): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
+    auth: AuthBearer,
+    Json(payload): Json<ChangePasswordPayload>,
+) -> impl IntoResponse {
+    let row = match auth_user_row(&app, &auth.0).await {
+        Ok(row) => row,
+        Err(_) => return (StatusCode::UNAUTHORIZED, Json(serde_json::json!({"error":"unauthorized"}))).into_response(),
+    };
+    if let Some(res) = recheck_password(&app, client_ip(&app.cfg, peer, &headers), &row, &payload.old_password).await {
+        return res;
+    }
+    if payload.new_password.len() < 4 {
+        return (StatusCode::BAD_REQUEST, Json(serde_json::json!({"error":"password_too_short"}))).into_response();
+    }
+    let hash = match hash_password_async(&app.cfg.argon2_params, &payload.new_password).await {
+        Ok(hash) => hash,
+        Err(e) => {
+            tracing::error!(target: "keldurben_server", event="hash_failed", error=%e);
+            return (StatusCode::INTERNAL_SERVER_ERROR, Json(serde_json::json!({"error":"hash_error"}))).into_response();
+        }
+    };
+    let res = sqlx::query("UPDATE users SET pwd_hash = ?1 WHERE id = ?2")
+        .bind(hash)
+        .bind(&row.id)
+        .execute(&app.db).await;
+    if res.is_err() {
+        return (StatusCode::INTERNAL_SERVER_ERROR, Json(serde_json::json!({"error":"db_error"}))).into_response();
+    }
+    StatusCode::NO_CONTENT.into_response()
+}
+
+async fn change_username(State(app): State<AppState>, auth: AuthBearer, Json(payload): Json<ChangeUsernamePayload>) -> impl IntoResponse {
+    let row = match auth_user_row(&app, &auth.0).await {
+        Ok(row) => row,
+        Err(_) => return (StatusCode::UNAUTHORIZED, Json(serde_json::json!({"error":"unauthorized"}))).into_response(),
+    };
+    let username = payload.username.trim().to_string();
+    if let Err(e) = validate_username(&username) {
+        return (StatusCode::BAD_REQUEST, Json(serde_json::json!({"error": e}))).into_response();
+    }
+    let res = sqlx::query("UPDATE users SET username = ?1, username_norm = ?2 WHERE id = ?3")
+        .bind(&username)
+        .bind(normalize_username(&username))
+        .bind(&row.id)
+        .execute(&app.db).await;
+    if let Err(e) = res {
+        if e.to_string().contains("UNIQUE") {
+            return (StatusCode::BAD_REQUEST, Json(serde_json::json!({"error":"username_taken"}))).into_response();
+        }
+        tracing::error!(target: "keldurben_server", event="db_error", error=%e);
+        return (StatusCode::INTERNAL_SERVER_ERROR, Json(serde_json::json!({"error":"db_error"}))).into_response();
+    }
+    let id = Uuid::parse_str(&row.id).unwrap_or_else(|_| Uuid::nil());
+
+    // Seats taken under the account name, and the name invites are sent with, follow the rename right away.
+    // A nickname picked when joining is left alone.
+    let mut hub = app.hub.lock().await;
+    if let Some(account) = hub.accounts.get_mut(&id) { account.username = username.clone(); }
+    let (old_avatar, new_avatar) = (default_avatar_url(&row.username), default_avatar_url(&username));
+    let mut touched = Vec::new();
+    for (name, room) in hub.rooms.iter_mut() {
+        for pl in room.players.iter_mut().filter(|p| p.user_id == Some(id) && p.name == row.username) {
+            pl.name = username.clone();
+            if pl.avatar.as_deref() == Some(old_avatar.as_str()) { pl.avatar = Some(new_avatar.clone()); }
+            touched.push(name.clone());
+        }
+    }
+    touched.dedup();
+    for room_name in touched { broadcast_state(room_name, &mut hub); }
+
+    let user = PublicUser::new(id, username, row.avatar);
+    (StatusCode::OK, Json(user)).into_response()
+}
+
+/// Deletes the account. Match history stays intact: the user's rows in
+/// `match_players` are unlinked and renamed so the other players' games still add up.
+/// The uploaded avatar is removed and the account's connections leave their rooms and are signed out.
+async fn delete_account(
+    State(app): State<AppState>,
+    ConnectInfo(peer): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
+    auth: AuthBearer,
+    Json(payload): Json<DeleteAccountPayload>,
+) -> impl IntoResponse {
+    let row = match auth_user_row(&app, &auth.0).await {
+        Ok(row) => row,
+        Err(_) => return (StatusCode::UNAUTHORIZED, Json(serde_json::json!({"error":"unauthorized"}))).into_response(),
+    };
+    if let Some(res) = recheck_password(&app, client_ip(&app.cfg, peer, &headers), &row, &payload.password).await {
+        return res;
+    }
+    let user_id = Uuid::parse_str(&row.id).unwrap_or_else(|_| Uuid::nil());
+    let friends: Vec<Uuid> = friend_ids(&app.db, user_id).await.unwrap_or_default().into_iter().collect();
+    if delete_user(&app.db, &row.id).await.is_err() {
+        return (StatusCode::INTERNAL_SERVER_ERROR, Json(serde_json::json!({"error":"db_error"}))).into_response();
+    }
+    if let Some(file) = row.avatar.as_deref().and_then(|a| a.strip_prefix("/media/avatars/")) {
+        let _ = tokio::fs::remove_file(avatar_dir(&app.cfg).join(file)).await;
+    }
+    forget_account(&mut *app.hub.lock().await, user_id);
+    refresh_friends(&app, &friends).await;
+    tracing::info!(target: "keldurben_server", event="account_deleted", user_id=%row.id);
+    StatusCode::NO_CONTENT.into_response()
+}
+
+/// Unseats and signs out every connection of a deleted account; its friends see it go offline.
+fn forget_account(hub: &mut WsHub, user_id: Uuid) {
+    let conns: Vec<Uuid> = hub.conn_users.iter().filter(|(_, u)| **u == user_id).map(|(c, _)| *c).collect();
+    for conn_id in conns {
+        leave_room(hub, conn_id);
+        hub.conn_users.remove(&conn_id);
+    }
+    update_presence(hub, user_id);
+    hub.accounts.remove(&user_id);
+    hub.presence.remove(&user_id);
+    hub.invites.retain(|_, inv| inv.from != user_id && inv.to != user_id);
+}
+
+async fn delete_user(db: &SqlitePool, user_id: &str) -> anyhow::Result<()> {
+    let mut tx = db.begin().await?;
+    sqlx::query("DELETE FROM user_settings WHERE user_id = ?1")
+        .bind(user_id)
+        .execute(&mut *tx).await?;
+    sqlx::query("DELETE FROM friendships WHERE user_id = ?1 OR friend_id = ?1")
+        .bind(user_id)
+        .execute(&mut *tx).await?;
+    sqlx::query("UPDATE match_players SET user_id = NULL, name = ?1 WHERE user_id = ?2")
+        .bind(DELETED_USER_NAME)
+        .bind(user_id)
+        .execute(&mut *tx).await?;
+    // The legacy name stays imported, so nobody else can claim it with the old password.
+    sqlx::query("UPDATE legacy_imports SET user_id = NULL WHERE user_id = ?1")
+        .bind(user_id)
+        .execute(&mut *tx).await?;
+    sqlx::query("DELETE FROM users WHERE id = ?1")
+        .bind(user_id)
+        .execute(&mut *tx).await?;
+    tx.commit().await?;
+    Ok(())
+}
+
+// ===================== REST: Avatars =====================
+/// Largest accepted upload body.
+const MAX_AVATAR_UPLOAD: usize = 4 * 1024 * 1024;
+/// Largest accepted source image edge, checked before decoding.
+const MAX_AVATAR_SOURCE_EDGE: u32 = 4096;
+/// Edge of the stored square avatar.
+const AVATAR_SIZE: u32 = 256;
+
+fn avatar_dir(cfg: &AppConfig) -> std::path::PathBuf {
+    std::path::Path::new(&cfg.data_dir).join("avatars")
+}
+
+/// Accepts a PNG, JPEG, WebP or GIF body, crops it to a square and stores it
+/// re-encoded as PNG. The previous upload is removed.
+async fn upload_avatar(State(app): State<AppState>, auth: AuthBearer, headers: HeaderMap, body: Bytes) -> impl IntoResponse {
+    let user = match auth_user(&app, &auth.0).await {
+        Ok(user) => user,
+        Err(_) => return (StatusCode::UNAUTHORIZED, Json(serde_json::json!({"error":"unauthorized"}))).into_response(),
+    };
+    let declared = headers.get(header::CONTENT_TYPE).and_then(|v| v.to_str().ok()).unwrap_or("");
+    if !declared.is_empty() && !declared.starts_with("image/") && declared != "application/octet-stream" {
+        return (StatusCode::UNSUPPORTED_MEDIA_TYPE, Json(serde_json::json!({"error":"unsupported_image"}))).into_response();
+    }
+    let png = match tokio::task::spawn_blocking(move || reencode_avatar(&body)).await {
+        Ok(Ok(png)) => png,
+        Ok(Err(e)) => {
+            let status = if e == "unsupported_image" { StatusCode::UNSUPPORTED_MEDIA_TYPE } else { StatusCode::BAD_REQUEST };
+            return (status, Json(serde_json::json!({"error": e}))).into_response();
+        }
+        Err(_) => return (StatusCode::INTERNAL_SERVER_ERROR, Json(serde_json::json!({"error":"image_error"}))).into_response(),
+    };
+
+    let file_name = format!("{}-{}.png", user.id, Uuid::new_v4().simple());
+    if tokio::fs::write(avatar_dir(&app.cfg).join(&file_name), png).await.is_err() {
+        return (StatusCode::INTERNAL_SERVER_ERROR, Json(serde_json::json!({"error":"storage_error"}))).into_response();
+    }
+    let url = format!("/media/avatars/{}", file_name);
+    let res = sqlx::query("UPDATE users SET avatar = ?1 WHERE id = ?2")
+        .bind(&url)
+        .bind(user.id.to_string())
+        .execute(&app.db).await;
+    if res.is_err() {
+        let _ = tokio::fs::remove_file(avatar_dir(&app.cfg).join(&file_name)).await;
+        return (StatusCode::INTERNAL_SERVER_ERROR, Json(serde_json::json!({"error":"db_error"}))).into_response();
+    }
+    if let Some(old) = user.avatar.as_deref().and_then(|a| a.strip_prefix("/media/avatars/")) {
+        let _ = tokio::fs::remove_file(avatar_dir(&app.cfg).join(old)).await;
+    }
+
+    // Players already seated in a room pick up the new picture right away.
+    let mut hub = app.hub.lock().await;
+    let mut touched = Vec::new();
+    for (name, room) in hub.rooms.iter_mut() {
+        for pl in room.players.iter_mut().filter(|p| p.user_id == Some(user.id)) {
+            pl.avatar = Some(url.clone());
+            touched.push(name.clone());
+        }
+    }
+    touched.dedup();
+    for room_name in touched { broadcast_state(room_name, &mut hub); }
+
+    let user = PublicUser { avatar: Some(url), ..user };
+    (StatusCode::OK, Json(user)).into_response()
+}
+
+fn reencode_avatar(data: &[u8]) -> Result<Vec<u8>, &'static str> {
+    use image::{ImageFormat, ImageReader, Limits};
+    let format = image::guess_format(data).map_err(|_| "unsupported_image")?;
+    if !matches!(format, ImageFormat::Png | ImageFormat::Jpeg | ImageFormat::WebP | ImageFormat::Gif) {
+        return Err("unsupported_image");
+    }
+    let mut reader = ImageReader::with_format(std::io::Cursor::new(data), format);
+    let mut limits = Limits::default();
+    limits.max_image_width = Some(MAX_AVATAR_SOURCE_EDGE);
+    limits.max_image_height = Some(MAX_AVATAR_SOURCE_EDGE);
+    reader.limits(limits);
+    let img = reader.decode().map_err(|e| match e {
+        image::ImageError::Limits(_) => "image_too_large",
+        _ => "invalid_image",
+    })?;
+    let thumb = img.resize_to_fill(AVATAR_SIZE, AVATAR_SIZE, image::imageops::FilterType::Lanczos3);
+    let mut out = std::io::Cursor::new(Vec::new());
+    thumb.to_rgba8().write_to(&mut out, ImageFormat::Png).map_err(|_| "invalid_image")?;
+    Ok(out.into_inner())
+}
+
+/// `GET /api/avatars/{username}.svg`: initials on a background colour derived
+/// from the name. The same name always yields the same bytes.
+async fn generated_avatar(Path(file): Path<String>) -> impl IntoResponse {
+    let Some(username) = file.strip_suffix(".svg") else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+    (
+        [
+            (header::CONTENT_TYPE, "image/svg+xml"),
+            (header::CACHE_CONTROL, "public, max-age=604800"),
+        ],
+        initials_avatar_svg(username),
+    ).into_response()
+}
+
+#[derive(Serialize)]
+struct BoardPalette { id: String, gradient: Gradient, cols: u32, rows: u32, cells: Vec<String> }
+
+/// Colours of every cell of a board, row by row. A board id always means the same colours, so responses never go stale.
+async fn board_palette(Path(id): Path<String>) -> impl IntoResponse {
+    let Some(board) = board::Board::from_id(&id) else {
+        return (StatusCode::NOT_FOUND, Json(serde_json::json!({"error":"unknown_board"}))).into_response();
+    };
+    (
+        [(header::CACHE_CONTROL, "public, max-age=31536000, immutable")],
+        Json(BoardPalette { id: board.id(), gradient: board.gradient, cols: board.cols, rows: board.rows, cells: board.palette() }),
+    ).into_response()
+}
+
+fn default_avatar_url(username: &str) -> String {
+    let mut encoded = String::with_capacity(username.len());
+    for b in username.bytes() {
+        if b.is_ascii_alphanumeric() || matches!(b, b'-' | b'_' | b'.' | b'~') {
+            encoded.push(b as char);
+        } else {
+            encoded.push_str(&format!("%{:02X}", b));
+        }
+    }
+    format!("/api/avatars/{}.svg", encoded)
+}
+
+/// FNV-1a; stable across builds and platforms, unlike `DefaultHasher`.
+fn stable_hash(s: &str) -> u64 {
+    s.bytes().fold(0xcbf29ce484222325u64, |h, b| (h ^ b as u64).wrapping_mul(0x100000001b3))
+}
+
+fn initials_avatar_svg(username: &str) -> String {
+    let hash = stable_hash(username);
+    let hue = hash % 360;
+    let hue2 = (hue + 40 + (hash >> 16) % 80) % 360;
+
+    let words: Vec<&str> = username
+        .split(|c: char| c.is_whitespace() || c == '_' || c == '-' || c == '.')
+        .filter(|w| !w.is_empty())
+        .collect();
+    let initials: String = match words.as_slice() {
+        [] => "?".into(),
+        [one] => one.chars().take(2).collect(),
+        [first, .., last] => first.chars().take(1).chain(last.chars().take(1)).collect(),
+    };
+    let mut text = String::new();
+    for c in initials.to_uppercase().chars() {
+        match c {
+            '&' => text.push_str("&amp;"),
+            '<' => text.push_str("&lt;"),
+            '>' => text.push_str("&gt;"),
+            '"' => text.push_str("&quot;"),
+            '\'' => text.push_str("&apos;"),
+            c => text.push(c),
+        }
+    }
+
+    format!(
+        concat!(
+            r#"<svg xmlns="http://www.w3.org/2000/svg" width="128" height="128" viewBox="0 0 128 128">"#,
+            r#"<defs><linearGradient id="g" x1="0" y1="0" x2="1" y2="1">"#,
+            r#"<stop offset="0" stop-color="hsl({h1},65%,55%)"/><stop offset="1" stop-color="hsl({h2},65%,40%)"/>"#,
+            r#"</linearGradient></defs>"#,
+            r#"<rect width="128" height="128" rx="64" fill="url(#g)"/>"#,
+            r#"<text x="64" y="64" dy=".35em" text-anchor="middle" font-family="Segoe UI, Roboto, sans-serif" font-size="52" font-weight="600" fill="white">{text}</text>"#,
+            r#"</svg>"#
+        ),
+        h1 = hue,
+        h2 = hue2,
+        text = text,
+    )
+}
+
+async fn debug_state(State(app): State<AppState>) -> impl IntoResponse {
+    let hub = app.hub.lock().await;
+    let mut rooms = serde_json::Map::new();
+    for (name, room) in hub.rooms.iter() {
+        rooms.insert(name.clone(), serde_json::json!({
+            "round": room.round,
+            "game": room.game.as_str(),
+            "phase": room.phase.as_str(),
+            "players": room.players.iter().map(|p| { serde_json::json!({"id": p.id, "name": p.name, "score": p.score}) }).collect::<Vec<_>>()
+        }));
+    }
+    let conns = hub.conns.len();
+    (StatusCode::OK, Json(serde_json::json!({"rooms": rooms, "conns": conns}))).into_response()
+}
+
+// ===================== REST: Health =====================
+/// How long a readiness probe may wait on the database or the hub lock.
+const READY_DEADLINE: Duration = Duration::from_secs(2);
+
+/// Liveness: the process is up and serving requests; nothing else is checked.
+async fn healthz(State(app): State<AppState>) -> impl IntoResponse {
+    (StatusCode::OK, Json(serde_json::json!({
+        "status": "ok",
+        "version": env!("CARGO_PKG_VERSION"),
+        "uptime_secs": app.started_at.elapsed().as_secs(),
+    }))).into_response()
+}
+
+/// Readiness: the database answers, its schema is current, and the hub lock is not stuck.
+async fn readyz(State(app): State<AppState>) -> impl IntoResponse {
+    let started = Instant::now();
+    let database = match tokio::time::timeout(READY_DEADLINE, sqlx::query("SELECT 1").execute(&app.db)).await {
+        Ok(Ok(_)) => serde_json::json!({"ok": true, "latency_ms": started.elapsed().as_millis() as u64}),
+        Ok(Err(e)) => serde_json::json!({"ok": false, "error": e.to_string()}),
+        Err(_) => serde_json::json!({"ok": false, "error": "timeout"}),
+    };
+    let latest = latest_schema_version();
+    let migrations = match tokio::time::timeout(READY_DEADLINE, schema_version(&app.db)).await {
+        Ok(Ok(v)) => serde_json::json!({"ok": v == latest, "version": v, "expected": latest}),
+        Ok(Err(e)) => serde_json::json!({"ok": false, "error": e.to_string(), "expected": latest}),
+        Err(_) => serde_json::json!({"ok": false, "error": "timeout", "expected": latest}),
+    };
+    let started = Instant::now();
+    let hub = match tokio::time::timeout(READY_DEADLINE, app.hub.lock()).await {
+        Ok(hub) => serde_json::json!({
+            "ok": true,
+            "lock_wait_ms": started.elapsed().as_millis() as u64,
+            "rooms": hub.rooms.len(),
+            "conns": hub.conns.len(),
+        }),
+        Err(_) => serde_json::json!({"ok": false, "error": "lock_timeout"}),
+    };
+    let ready = [&database, &migrations, &hub].iter().all(|c| c["ok"] == true);
+    let status = if ready { StatusCode::OK } else { StatusCode::SERVICE_UNAVAILABLE };
+    if !ready {
+        tracing::warn!(target: "keldurben_server", event="not_ready", database=%database, migrations=%migrations, hub=%hub);
+    }
+    (status, Json(serde_json::json!({
+        "status": if ready { "ready" } else { "not_ready" },
+        "checks": { "database": database, "migrations": migrations, "hub": hub },
+    }))).into_response()
+}
+
+// ===================== REST: Admin =====================
+#[derive(Deserialize)]
+struct AdminResetPayload { secret: String }
+
+#[derive(Deserialize)]
+struct AdminKickPayload { secret: String, player: Uuid }
+
+async fn admin_reset(State(app): State<AppState>, Json(payload): Json<AdminResetPayload>) -> impl IntoResponse {
+    if payload.secret != app.cfg.admin_secret { return StatusCode::FORBIDDEN.into_response(); }
+    let mut hub = app.hub.lock().await;
+    if let Some(room) = hub.rooms.get_mut("default") {
+        *room = default_room();
+    }
+    broadcast_state("default".into(), &mut hub);
+    StatusCode::OK.into_response()
+}
+
+async fn admin_kick(State(app): State<AppState>, Json(payload): Json<AdminKickPayload>) -> impl IntoResponse {
+    if payload.secret != app.cfg.admin_secret { return StatusCode::FORBIDDEN.into_response(); }
+    let mut hub = app.hub.lock().await;
+    apply_to_room(&mut hub, "default", payload.player, game::Command::Leave);
+    StatusCode::OK.into_response()
+}
+
+// ===================== WS =====================
+async fn ws_handler(ws: WebSocketUpgrade, State(app): State<AppState>) -> impl IntoResponse {
+    let conn_id = Uuid::new_v4();
+    // `player_id`, `room` and `user_id` are filled in as the connection joins and signs in.
+    let span = tracing::info_span!("conn", conn_id = %conn_id, player_id = tracing::field::Empty, room = tracing::field::Empty, user_id = tracing::field::Empty);
+    ws.on_upgrade(move |socket| handle_socket(socket, app, conn_id).instrument(span))
+}
+
+async fn handle_socket(socket: WebSocket, app: AppState, conn_id: Uuid) {
+    let (tx, mut rx) = socket.split();
+    let (msg_tx, msg_rx) = tokio::sync::mpsc::unbounded_channel::<Message>();
+    let mut tx = tx;
+
+    let forward = tokio::spawn(async move {
+        let mut msg_rx = UnboundedReceiverStream::new(msg_rx);
+        while let Some(msg) = tokio_stream::StreamExt::next(&mut msg_rx).await {
+            if tx.send(msg).await.is_err() { break; }
+        }
+    });
+
+    {
+        let mut hub = app.hub.lock().await;
+        hub.txs.insert(conn_id, msg_tx.clone());
+    }
+    METRICS.ws_connections.inc();
+    tracing::debug!(target: "keldurben_server", event="ws_connect");
+
+    while let Some(Ok(msg)) = futures_util::StreamExt::next(&mut rx).await {
+        if let Message::Text(text) = msg {
+            match serde_json::from_str::<ClientEnvelope>(&text) {
+                Ok(ClientEnvelope { msg_id, session, expect_round, expect_phase, msg: cmd }) => {
+                    let kind = cmd.kind();
+                    METRICS.messages_in.with_label_values(&[kind]).inc();
+                    // Keyed before the message runs: a join changes who the connection is.
+                    let mut reply_to = None;
+                    if let Some(id) = msg_id.as_deref() {
+                        let mut hub = app.hub.lock().await;
+                        let key = reply_key(&hub, conn_id, session.as_deref());
+                        let recent = hub.replies.entry(key.clone()).or_default();
+                        match recent.get(id) {
+                            Some(Some(reply)) => {
+                                tracing::debug!(target: "keldurben_server", event="duplicate_msg", kind=%kind);
+                                send_reply(&msg_tx, msg_id.clone(), reply.clone());
+                                continue;
+                            }
+                            // The first copy is still running and is answered on its own.
+                            Some(None) => {
+                                tracing::debug!(target: "keldurben_server", event="duplicate_msg_in_flight", kind=%kind);
+                                continue;
+                            }
+                            None => recent.reserve(id.to_string()),
+                        }
+                        reply_to = Some(key);
+                    }
+                    let started = Instant::now();
+                    let expect = Expect { round: expect_round, phase: expect_phase };
+                    let reply = handle_client_msg(conn_id, cmd, &expect, &app).await;
+                    METRICS.ws_handler_latency.with_label_values(&[kind]).observe(started.elapsed().as_secs_f64());
+                    send_reply(&msg_tx, msg_id.clone(), reply.clone());
+                    if let (Some(msg_id), Some(key)) = (msg_id, reply_to) {
+                        app.hub.lock().await.replies.entry(key).or_default().finish(&msg_id, reply);
+                    }
+                }
+                Err(e) => {
+                    METRICS.messages_in.with_label_values(&["invalid"]).inc();
+                    deliver(&msg_tx, "error", serde_json::to_string(&ServerMsg::Error{ message: format!("bad json: {}", e)}).unwrap());
+                }
+            }
+        }
+    }
+    METRICS.ws_connections.dec();
+    tracing::debug!(target: "keldurben_server", event="ws_disconnect");
+
+    // cleanup
+    {
+        let mut hub = app.hub.lock().await;
+        hub.txs.remove(&conn_id);
+        let user_id = hub.conn_users.remove(&conn_id);
+        leave_room(&mut hub, conn_id);
+        hub.replies.remove(&format!("conn:{}", conn_id));
+        hub.replies.retain(|_, r| r.last_used.elapsed() < RecentReplies::TTL);
+        if let Some(user_id) = user_id {
+            update_presence(&mut hub, user_id);
+            if !hub.conn_users.values().any(|u| *u == user_id) { hub.accounts.remove(&user_id); }
+        }
+    }
+
+    forward.abort();
+}
+
+/// Whose recent replies a message shares: those of the client `session` it names, within the signed-in account,
+/// so a client that reconnects and resends is still recognised while other tabs and devices keep their own.
+/// Without a session the connection has its own.
+fn reply_key(hub: &WsHub, conn_id: Uuid, session: Option<&str>) -> String {
+    match (session, hub.conn_users.get(&conn_id)) {
+        (Some(session), Some(user_id)) => format!("user:{}:{}", user_id, session),
+        (Some(session), None) => format!("guest:{}", session),
+        (None, _) => format!("conn:{}", conn_id),
+    }
+}
+
+/// `ack`/`reject` when the client numbered its message; otherwise only a refusal is answered, with `error`.
+fn send_reply(tx: &tokio::sync::mpsc::UnboundedSender<Message>, msg_id: Option<String>, reply: Result<(), String>) {
+    let msg = match (msg_id, reply) {
+        (Some(msg_id), Ok(())) => ServerMsg::Ack { msg_id },
+        (Some(msg_id), Err(reason)) => ServerMsg::Reject { msg_id, reason },
+        (None, Err(message)) => ServerMsg::Error { message },
+        (None, Ok(())) => return,
+    };
+    deliver(tx, msg.kind(), serde_json::to_string(&msg).unwrap());
+}
+
+/// Carries out one client message. `Err` is the reason it was refused, for the caller to send back.
+async fn handle_client_msg(conn_id: Uuid, cmd: ClientMsg, expect: &Expect, app: &AppState) -> Result<(), String> {
+    match cmd {
+        ClientMsg::Join { name, room, token, private, board } => {
+            // Используем явную комнату или 'default' — БЕЗ хитрой логики группировки
+            let room_name = room.unwrap_or_else(|| "default".into());
+            let account = match token {
+                Some(token) => load_account(app, &token).await,
+                None => None,
+            };
+            
+            let mut hub = app.hub.lock().await;
+            let board = match board.as_deref().map(board::Board::from_id) {
+                Some(None) => {
+                    return Err("unknown board".into());
+                }
+                // The public rooms keep the classic board whoever creates them.
+                Some(_) if PUBLIC_ROOMS.contains(&room_name.as_str()) => None,
+                Some(board) => board,
+                None => None,
+            };
+            if let Some((user, friends)) = account {
+                attach_account(&mut hub, conn_id, user, friends);
+            }
+            if private == Some(true) && PUBLIC_ROOMS.contains(&room_name.as_str()) {
+                return Err("room is public".into());
+            }
+            if hub.rooms.get(&room_name).is_some_and(|r| r.private) {
+                return Err("room is private".into());
+            }
+            join_room(&mut hub, conn_id, name, room_name, private.unwrap_or(false), board)?;
+        }
+        ClientMsg::Identify { token } => {
+            let account = load_account(app, &token).await;
+            let mut hub = app.hub.lock().await;
+            match account {
+                Some((user, friends)) => attach_account(&mut hub, conn_id, user, friends),
+                None => return Err("invalid token".into()),
+            }
+        }
+        ClientMsg::Invite { user_id } => {
+            let mut hub = app.hub.lock().await;
+            let from = hub.conn_users.get(&conn_id).copied();
+            let room_name = hub.conns.get(&conn_id).map(|(r, _)| r.clone());
+            let (Some(from), Some(room_name)) = (from, room_name) else {
+                return Err("sign in and join a room to invite".into());
+            };
+            let Some(account) = hub.accounts.get(&from) else { return Ok(()) };
+            if !account.friends.contains(&user_id) {
+                return Err("only friends can be invited".into());
+            }
+            if !hub.accounts.contains_key(&user_id) {
+                return Err("friend is offline".into());
+            }
+            let from_name = account.username.clone();
+            hub.invites.retain(|_, inv| inv.created.elapsed() < INVITE_TTL);
+            let invite_id = Uuid::new_v4();
+            hub.invites.insert(invite_id, RoomInvite { from, to: user_id, room: room_name.clone(), created: std::time::Instant::now() });
+            tracing::info!(target: "keldurben_server", event="invite", from=%from, to=%user_id, room=%room_name);
+            send_to_user(&hub, user_id, &ServerMsg::Invite { invite_id, from, from_name, room: room_name });
+        }
+        ClientMsg::AcceptInvite { invite_id, name } => {
+            let mut hub = app.hub.lock().await;
+            let me = hub.conn_users.get(&conn_id).copied();
+            let valid = hub.invites.get(&invite_id)
+                .is_some_and(|inv| Some(inv.to) == me && inv.created.elapsed() < INVITE_TTL && hub.rooms.contains_key(&inv.room));
+            let (Some(me), true) = (me, valid) else {
+                return Err("invite expired".into());
+            };
+            let Some(invite) = hub.invites.remove(&invite_id) else { return Ok(()) };
+            let name = name
+                .or_else(|| hub.accounts.get(&me).map(|a| a.username.clone()))
+                .unwrap_or_default();
+            join_room(&mut hub, conn_id, name, invite.room, false, None)?;
+        }
+        ClientMsg::DeclineInvite { invite_id } => {
+            let mut hub = app.hub.lock().await;
+            let Some(me) = hub.conn_users.get(&conn_id).copied() else { return Ok(()) };
+            if hub.invites.get(&invite_id).is_some_and(|inv| inv.to == me) {
+                if let Some(invite) = hub.invites.remove(&invite_id) {
+                    send_to_user(&hub, invite.from, &ServerMsg::InviteDeclined { invite_id, by: me });
+                }
+            }
+        }
+        ClientMsg::AddBot { difficulty, lang } => {
+            let mut hub = app.hub.lock().await;
+            let Some((room_name, _)) = hub.conns.get(&conn_id).cloned() else {
+                return Err("join a room first".into());
+            };
+            if hub.rooms.get(&room_name).is_some_and(|r| r.game != GameKind::HuesAndCues) {
+                return Err("bots only play hues and cues".into());
+            }
+            let bots_here = hub.conns.iter().filter(|(c, (r, _))| *r == room_name && hub.bots.contains_key(c)).count();
+            if bots_here >= bots::MAX_BOTS_PER_ROOM {
+                return Err("too many bots".into());
+            }
+            if hub.rooms.get(&room_name).is_some_and(room_is_full) {
+                return Err("room is full".into());
+            }
+            let (difficulty, lang) = (difficulty.unwrap_or_default(), lang.unwrap_or_default());
+            let bot_conn = Uuid::new_v4();
+            hub.bots.insert(bot_conn, bots::Bot::new(difficulty, lang));
+            tracing::info!(target: "keldurben_server", event="add_bot", room=%room_name, difficulty=?difficulty);
+            if let Err(reason) = join_room(&mut hub, bot_conn, bots::name(lang, difficulty), room_name, false, None) {
+                hub.bots.remove(&bot_conn);
+                return Err(reason);
+            }
+        }
+        ClientMsg::RemoveBot { player } => {
+            let mut hub = app.hub.lock().await;
+            let Some((room_name, _)) = hub.conns.get(&conn_id).cloned() else { return Ok(()) };
+            let bot_conn = hub.conns.iter()
+                .find(|(c, (r, p))| *r == room_name && *p == player && hub.bots.contains_key(c))
+                .map(|(c, _)| *c);
+            match bot_conn {
+                Some(bot_conn) => {
+                    hub.bots.remove(&bot_conn);
+                    leave_room(&mut hub, bot_conn);
+                }
+                None => return Err("no such bot".into()),
+            }
+        }
+        ClientMsg::StartGame { force } => apply_command(&mut *app.hub.lock().await, conn_id, game::Command::StartGame { force }, expect)?,
+        ClientMsg::SetReady { ready } => apply_command(&mut *app.hub.lock().await, conn_id, game::Command::SetReady { ready }, expect)?,
+        ClientMsg::LockCue1 { cue } => apply_command(&mut *app.hub.lock().await, conn_id, game::Command::LockCue1 { cue }, expect)?,
+        ClientMsg::LockCue2 { cue2 } => apply_command(&mut *app.hub.lock().await, conn_id, game::Command::LockCue2 { cue2 }, expect)?,
+        ClientMsg::PassCue => apply_command(&mut *app.hub.lock().await, conn_id, game::Command::PassCue, expect)?,
+        ClientMsg::ChooseTarget { index } => apply_command(&mut *app.hub.lock().await, conn_id, game::Command::ChooseTarget { index }, expect)?,
+        ClientMsg::Guess { cell } => apply_command(&mut *app.hub.lock().await, conn_id, game::Command::Guess { cell }, expect)?,
+        ClientMsg::NextRound => apply_command(&mut *app.hub.lock().await, conn_id, game::Command::NextRound, expect)?,
+        ClientMsg::SetCueRules { rules } => apply_command(&mut *app.hub.lock().await, conn_id, game::Command::SetCueRules { rules }, expect)?,
+        ClientMsg::SetScoring { scoring } => apply_command(&mut *app.hub.lock().await, conn_id, game::Command::SetScoring { scoring }, expect)?,
+        ClientMsg::SetGameLength { length } => apply_command(&mut *app.hub.lock().await, conn_id, game::Command::SetGameLength { length }, expect)?,
+        ClientMsg::SetPlayerLimits { limits } => apply_command(&mut *app.hub.lock().await, conn_id, game::Command::SetPlayerLimits { limits }, expect)?,
+        ClientMsg::Rematch => apply_command(&mut *app.hub.lock().await, conn_id, game::Command::Rematch, expect)?,
+        ClientMsg::AdminReset { secret } => {
+            if secret != app.cfg.admin_secret { return Err("forbidden".into()); }
+            let mut hub = app.hub.lock().await;
+            if let Some(room) = hub.rooms.get_mut("default") { *room = default_room(); }
+            broadcast_state("default".into(), &mut hub);
+        }
+        ClientMsg::AdminKick { secret, player } => {
+            if secret != app.cfg.admin_secret { return Err("forbidden".into()); }
+            let mut hub = app.hub.lock().await;
+            apply_to_room(&mut hub, "default", player, game::Command::Leave);
+        }
+    }
+    Ok(())
+}
+
+fn broadcast_state(room_name: String, hub: &mut WsHub) {
+    let mut accounts = Vec::new();
+    if let Some(room) = hub.rooms.get(&room_name) {
+        accounts.extend(room.players.iter().filter_map(|p| p.user_id));
+        let dto = GameStateDto {
+            room: room_name.clone(),
+            game: room.game,
+            round: room.round,
+            cols: room.board.cols,
+            rows: room.board.rows,
+            board_id: room.board.id(),
+            cue_giver: room.players.get(room.cue_giver_idx).map(|p| p.id),
+            host: room.players.first().map(|p| p.id),
+            phase: room.phase.as_str().into(),
+            cue1: room.cue1.clone(),
+            cue2: room.cue2.clone(),
+            target: if matches!(room.phase, Phase::Reveal | Phase::GameOver) { room.target } else { None },
+            select_options: room.select_options.clone(),
+            cue_rules: room.cue_rules.clone(),
+            scoring: room.scoring,
+            round_result: room.round_result.clone(),
+            game_length: room.game_length.clone(),
+            standings: room.standings.clone(),
+            player_limits: room.limits,
+            start_blockers: if matches!(room.phase, Phase::Lobby | Phase::GameOver) {
+                game::start_blockers(room).into_iter().map(String::from).collect()
+            } else {
+                Vec::new()
+            },
+            players: room.players.iter().map(|p| PlayerDto{ id: p.id, name: p.name.clone(), score: p.score, avatar: p.avatar.clone(), user_id: p.user_id, friend: false, bot: p.bot, ready: p.ready }).collect(),
+            guessed_once: room.guessed_once.clone(),
+            guessed_twice: room.guessed_twice.clone(),
+            guesses1: room.guess1_cells.iter().map(|(k,v)| (*k, *v)).collect(),
+            guesses2: room.guess2_cells.iter().map(|(k,v)| (*k, *v)).collect(),
+            last_guesses: room.guess2_cells.iter().map(|(k,v)| (*k, *v)).collect(),
+        };
+        let _room = room_span(&room_name, room).entered();
+        tracing::info!(target: "keldurben_server", event="broadcast_state", players=%room.players.len(), phase=%room.phase.as_str(), round=%room.round);
+        let has_accounts = room.players.iter().any(|p| p.user_id.is_some());
+        let msg = serde_json::to_string(&ServerMsg::State{ state: Box::new(dto.clone()) }).unwrap();
+        let mut fanout = 0;
+        for (_cid, (rname, _pid)) in hub.conns.iter() {
+            if rname == &room_name {
+                fanout += 1;
+                let Some(tx) = hub.txs.get(_cid) else { continue };
+                let friends = hub.conn_users.get(_cid).and_then(|uid| hub.accounts.get(uid)).map(|a| &a.friends).filter(|f| has_accounts && !f.is_empty());
+                match friends {
+                    Some(friends) => {
+                        let mut own = dto.clone();
+                        for p in own.players.iter_mut() {
+                            p.friend = p.user_id.is_some_and(|uid| friends.contains(&uid));
+                        }
+                        deliver(tx, "state", serde_json::to_string(&ServerMsg::State{ state: Box::new(own) }).unwrap());
+                    }
+                    None => deliver(tx, "state", msg.clone()),
+                }
+            }
+        }
+        METRICS.broadcast_fanout.observe(fanout as f64);
+    }
+    for user_id in accounts { update_presence(hub, user_id); }
+}
+
+/// Queues a frame for a connection's writer task and counts it.
+fn deliver(tx: &tokio::sync::mpsc::UnboundedSender<Message>, kind: &'static str, text: String) {
+    METRICS.messages_out.with_label_values(&[kind]).inc();
+    let _ = tx.send(Message::Text(text));
+}
+
+fn send_msg(hub: &WsHub, conn_id: Uuid, msg: &ServerMsg) {
+    if let Some(tx) = hub.txs.get(&conn_id) {
+        deliver(tx, msg.kind(), serde_json::to_string(msg).unwrap());
+    }
+}
+
+/// Sends to every connection signed in as `user_id`.
+fn send_to_user(hub: &WsHub, user_id: Uuid, msg: &ServerMsg) {
+    let text = serde_json::to_string(msg).unwrap();
+    for (cid, uid) in hub.conn_users.iter() {
+        if *uid == user_id {
+            if let Some(tx) = hub.txs.get(cid) { deliver(tx, msg.kind(), text.clone()); }
+        }
+    }
+}
+
+/// Seats the connection in `room_name`, creating the room if needed, and announces it.
+fn join_room(hub: &mut WsHub, conn_id: Uuid, name: String, room_name: String, private: bool, board: Option<board::Board>) -> Result<(), String> {
+    let already_here = hub.conns.get(&conn_id).is_some_and(|(r, _)| *r == room_name);
+    if !already_here && hub.rooms.get(&room_name).is_some_and(room_is_full) {
+        return Err("room is full".into());
+    }
+    leave_room(hub, conn_id);
+    let player_id = Uuid::new_v4();
+    let user_id = hub.conn_users.get(&conn_id).copied();
+    let avatar = user_id
+        .and_then(|uid| hub.accounts.get(&uid))
+        .and_then(|a| a.avatar.clone())
+        .or_else(|| Some(default_avatar_url(&name)));
+    let room_entry = hub.rooms.entry(room_name.clone())
+        .or_insert_with(|| RoomState {
+            name: room_name.clone(),
+            private,
+            game: GameKind::for_room(&room_name),
+            board: board.unwrap_or(board::Board::CLASSIC),
+            limits: GameKind::for_room(&room_name).player_limits(),
+            ..default_room()
+        });
+    let total_players = room_entry.players.len() + 1;
+    hub.conns.insert(conn_id, (room_name.clone(), player_id));
+
+    tracing::Span::current().record("room", room_name.as_str()).record("player_id", tracing::field::display(player_id));
+    tracing::info!(target: "keldurben_server", event="join", name=%name, room=%room_name, player_id=%player_id, total_players=%total_players);
+    send_msg(hub, conn_id, &ServerMsg::Welcome { id: player_id, room: room_name.clone() });
+    let bot = hub.bots.contains_key(&conn_id);
+    apply_command(hub, conn_id, game::Command::Join { name, user_id, avatar, bot }, &Expect::default())
+}
+
+fn room_is_full(room: &RoomState) -> bool {
+    room.players.len() >= room.limits.max as usize
+}
+
+/// Root span for events about a room as a whole rather than one connection.
+fn room_span(room_name: &str, room: &RoomState) -> tracing::Span {
+    tracing::info_span!(parent: None, "room", room = %room_name, game = room.game.as_str())
+}
+
+fn leave_room(hub: &mut WsHub, conn_id: Uuid) {
+    if let Some((room_name, player_id)) = hub.conns.remove(&conn_id) {
+        apply_to_room(hub, &room_name, player_id, game::Command::Leave);
+        // Bots do not keep a room going on their own.
+        let seated: Vec<Uuid> = hub.conns.iter().filter(|(_, (r, _))| *r == room_name).map(|(c, _)| *c).collect();
+        if seated.iter().all(|c| hub.bots.contains_key(c)) {
+            for bot in seated {
+                hub.bots.remove(&bot);
+                if let Some((_, player_id)) = hub.conns.remove(&bot) {
+                    apply_to_room(hub, &room_name, player_id, game::Command::Leave);
+                }
+            }
+            // Nobody can find an empty private room again; free its name.
+            if hub.rooms.get(&room_name).is_some_and(|r| r.private) {
+                hub.rooms.remove(&room_name);
+                hub.invites.retain(|_, inv| inv.room != room_name);
+            }
+        }
+    }
+}
+
+/// Lets every bot whose turn it is act, through the same path as a client command.
+fn drive_bots(hub: &mut WsHub) {
+    let now = Instant::now();
+    let ids: Vec<Uuid> = hub.bots.keys().copied().collect();
+    for conn_id in ids {
+        let Some((room_name, player_id)) = hub.conns.get(&conn_id).cloned() else {
+            hub.bots.remove(&conn_id);
+            continue;
+        };
+        let (Some(room), Some(bot)) = (hub.rooms.get(&room_name), hub.bots.get_mut(&conn_id)) else { continue };
+        if let Some(cmd) = bot.poll(room, player_id, now) {
+            // A bot acts on the state it just looked at, so a refusal only means it will think again.
+            let _ = apply_command(hub, conn_id, cmd, &Expect::default());
+        }
+    }
+}
+
+/// Runs a command from the connection's seat through the rules, unless the room has moved on from what
+/// the client `expect`ed. A rejection is returned for the caller to answer the connection with.
+fn apply_command(hub: &mut WsHub, conn_id: Uuid, cmd: game::Command, expect: &Expect) -> Result<(), String> {
+    let Some((room_name, player_id)) = hub.conns.get(&conn_id).cloned() else { return Err("join a room first".into()) };
+    if let Some(room) = hub.rooms.get(&room_name) {
+        let round_moved = expect.round.is_some_and(|r| r != room.round);
+        let phase_moved = expect.phase.as_deref().is_some_and(|p| p != room.phase.as_str());
+        if round_moved || phase_moved { return Err("stale command".into()); }
+    }
+    let mut result = Ok(());
+    for effect in run_reducer(hub, &room_name, player_id, cmd) {
+        match effect {
+            game::Effect::Broadcast => broadcast_state(room_name.clone(), hub),
+            game::Effect::Reject(reason) => result = Err(reason.to_string()),
+        }
+    }
+    result
+}
+
+/// Like `apply_command` for a player without a connection, e.g. one being removed; rejections are dropped.
+fn apply_to_room(hub: &mut WsHub, room_name: &str, player_id: Uuid, cmd: game::Command) {
+    for effect in run_reducer(hub, room_name, player_id, cmd) {
+        if effect == game::Effect::Broadcast { broadcast_state(room_name.to_string(), hub); }
+    }
+}
+
+fn run_reducer(hub: &mut WsHub, room_name: &str, player_id: Uuid, cmd: game::Command) -> Vec<game::Effect> {
+    let Some(room) = hub.rooms.remove(room_name) else { return Vec::new() };
+    let before = room.phase;
+    let (mut room, effects) = game::reduce(room, player_id, cmd);
+    track_match(hub, &mut room, before);
+    hub.rooms.insert(room_name.to_string(), room);
+    effects
+}
+
+/// Notes when a game starts and hands it to the match writer once it is over.
+fn track_match(hub: &WsHub, room: &mut RoomState, before: Phase) {
+    let now = time::OffsetDateTime::now_utc().unix_timestamp();
+    if matches!(before, Phase::Lobby | Phase::GameOver) && room.phase == Phase::Cue1 {
+        room.started_at = Some(now);
+    }
+    if room.phase != Phase::GameOver || before == Phase::GameOver { return; }
+    let (Some(log), Some(started_at)) = (&hub.match_log, room.started_at.take()) else { return };
+    let Some(standings) = &room.standings else { return };
+    let players = standings.iter()
+        .map(|s| MatchPlayer {
+            user_id: room.players.iter().find(|p| p.id == s.player).and_then(|p| p.user_id),
+            name: s.name.clone(),
+            score: s.score,
+            place: s.place,
+        })
+        .collect();
+    let _ = log.send(FinishedMatch { room: room.name.clone(), started_at, ended_at: now, players });
+}
+
+async fn record_match(db: &SqlitePool, finished: FinishedMatch) -> anyhow::Result<()> {
+    let match_id = Uuid::new_v4().to_string();
+    let mut tx = db.begin().await?;
+    sqlx::query("INSERT INTO matches (id, room, started_at, ended_at) VALUES (?1, ?2, ?3, ?4)")
+        .bind(&match_id)
+        .bind(&finished.room)
+        .bind(finished.started_at)
+        .bind(finished.ended_at)
+        .execute(&mut *tx).await?;
+    for p in finished.players {
+        sqlx::query("INSERT INTO match_players (match_id, user_id, name, score, place) VALUES (?1, ?2, ?3, ?4, ?5)")
+            .bind(&match_id)
+            .bind(p.user_id.map(|id| id.to_string()))
+            .bind(p.name)
+            .bind(p.score)
+            .bind(p.place)
+            .execute(&mut *tx).await?;
+    }
+    timed("record_match", tx.commit()).await?;
+    Ok(())
+}
+
+/// Resolves a token into the account and its friend ids, ahead of taking the hub lock.
+async fn load_account(app: &AppState, token: &str) -> Option<(PublicUser, HashSet<Uuid>)> {
+    let user = auth_user(app, token).await.ok()?;
+    let friends = friend_ids(&app.db, user.id).await.unwrap_or_default();
+    Some((user, friends))
+}
+
+fn attach_account(hub: &mut WsHub, conn_id: Uuid, user: PublicUser, friends: HashSet<Uuid>) {
+    tracing::Span::current().record("user_id", tracing::field::display(user.id));
+    hub.conn_users.insert(conn_id, user.id);
+    hub.accounts.insert(user.id, OnlineAccount { username: user.username, avatar: user.avatar, friends });
+    send_msg(hub, conn_id, &ServerMsg::Presence { friends: presence_snapshot(hub, user.id) });
+    update_presence(hub, user.id);
+}
+
+fn presence_of(hub: &WsHub, user_id: Uuid) -> FriendPresence {
+    let mut presence = FriendPresence { user_id, online: false, room: None, in_game: false };
+    for (cid, _) in hub.conn_users.iter().filter(|(_, uid)| **uid == user_id) {
+        presence.online = true;
+        if let Some((room_name, _)) = hub.conns.get(cid) {
+            presence.in_game = hub.rooms.get(room_name).is_some_and(|r| r.phase != Phase::Lobby);
+            presence.room = Some(room_name.clone());
+            break;
+        }
+    }
+    presence
+}
+
+fn presence_snapshot(hub: &WsHub, user_id: Uuid) -> Vec<FriendPresence> {
+    hub.accounts.get(&user_id)
+        .map(|a| a.friends.iter().map(|f| presence_of(hub, *f)).collect())
+        .unwrap_or_default()
+}
+
+/// Tells the account's online friends if its presence changed since the last announcement.
+fn update_presence(hub: &mut WsHub, user_id: Uuid) {
+    let presence = presence_of(hub, user_id);
+    let unchanged = match hub.presence.get(&user_id) {
+        Some(last) => *last == presence,
+        None => !presence.online,
+    };
+    if unchanged { return; }
+    if presence.online { hub.presence.insert(user_id, presence.clone()); } else { hub.presence.remove(&user_id); }
+    let Some(account) = hub.accounts.get(&user_id) else { return };
+    let msg = ServerMsg::PresenceUpdate { presence };
+    for friend in account.friends.iter() { send_to_user(hub, *friend, &msg); }
+}
+
+// ===================== Usernames =====================
+const USERNAME_MIN_CHARS: usize = 3;
+const USERNAME_MAX_CHARS: usize = 24;
+
+/// Compared after normalisation, so lookalikes of these are refused too.
+const RESERVED_USERNAMES: &[&str] = &[
+    "admin", "administrator", "moderator", "mod", "system", "server", "root", "support",
+    "keldurben", "guest", "deleted", "deleted_user", "anonymous", "null", "undefined", "me", "api",
+];
+
+/// Letters from one script (Latin or Cyrillic), digits and `_-.`, starting with a letter or digit.
+fn validate_username(name: &str) -> Result<(), &'static str> {
+    let len = name.chars().count();
+    if len < USERNAME_MIN_CHARS { return Err("username_too_short"); }
+    if len > USERNAME_MAX_CHARS { return Err("username_too_long"); }
+    let (mut latin, mut cyrillic) = (false, false);
+    for c in name.chars() {
+        match c {
+            'a'..='z' | 'A'..='Z' => latin = true,
+            '\u{0400}'..='\u{04FF}' if c.is_alphabetic() => cyrillic = true,
+            '0'..='9' | '_' | '-' | '.' => {}
+            _ => return Err("username_invalid_chars"),
+        }
+    }
+    if latin && cyrillic { return Err("username_mixed_scripts"); }
+    if !name.starts_with(|c: char| c.is_alphanumeric()) { return Err("username_invalid_start"); }
+    let norm = normalize_username(name);
+    if RESERVED_USERNAMES.iter().any(|r| normalize_username(r) == norm) { return Err("username_reserved"); }
+    Ok(())
+}
+
+/// Case- and lookalike-insensitive key stored in `users.username_norm`: Cyrillic
+/// homoglyphs fold to Latin, `0`/`1` to `o`/`l`, and separators to `_`.
+fn normalize_username(name: &str) -> String {
+    name.to_lowercase()
+        .chars()
+        .map(|c| match c {
+            'а' => 'a', 'в' => 'b', 'е' | 'ё' => 'e', 'к' => 'k', 'м' => 'm', 'н' => 'h',
+            'о' => 'o', 'р' => 'p', 'с' => 'c', 'т' => 't', 'у' => 'y', 'х' => 'x',
+            'і' => 'i', 'ј' => 'j', 'ѕ' => 's',
+            '0' => 'o', '1' => 'l',
+            '-' | '.' => '_',
+            c => c,
+        })
+        .collect()
+}
+
+/// Looks an account up the way it was typed at login, ignoring case and lookalikes.
+/// `username_norm` is unique and filled for every account (see `backfill_username_norm`), so at most one matches.
+async fn find_user_by_name(db: &SqlitePool, name: &str) -> Result<Option<UserRow>, sqlx::Error> {
+    timed("find_user_by_name", sqlx::query_as::<_, UserRow>(
+        "SELECT id, username, pwd_hash, avatar FROM users WHERE username_norm = ?1")
+        .bind(normalize_username(name.trim()))
+        .fetch_optional(db)).await
+}
+
+// ===================== Metrics =====================
+struct Metrics {
+    registry: prometheus::Registry,
+    ws_connections: prometheus::IntGauge,
+    rooms: prometheus::IntGaugeVec,
+    messages_in: prometheus::IntCounterVec,
+    messages_out: prometheus::IntCounterVec,
+    broadcast_fanout: prometheus::Histogram,
+    auth_attempts: prometheus::IntCounterVec,
+    db_latency: prometheus::HistogramVec,
+    ws_handler_latency: prometheus::HistogramVec,
+}
+
+impl Metrics {
+    fn new() -> prometheus::Result<Self> {
+        use prometheus::{Histogram, HistogramOpts, HistogramVec, IntCounterVec, IntGauge, IntGaugeVec, Opts};
+        let registry = prometheus::Registry::new();
+        let ws_connections = IntGauge::new("keldurben_ws_connections", "Open WebSocket connections")?;
+        let rooms = IntGaugeVec::new(Opts::new("keldurben_rooms", "Rooms by game and phase"), &["game", "phase"])?;
+        let messages_in = IntCounterVec::new(Opts::new("keldurben_ws_messages_in_total", "Client messages received by type"), &["type"])?;
+        let messages_out = IntCounterVec::new(Opts::new("keldurben_ws_messages_out_total", "Server messages sent by type"), &["type"])?;
+        let broadcast_fanout = Histogram::with_opts(
+            HistogramOpts::new("keldurben_broadcast_fanout", "Connections reached per state broadcast")
+                .buckets(vec![1.0, 2.0, 4.0, 6.0, 8.0, 12.0, 16.0, 32.0]))?;
+        let auth_attempts = IntCounterVec::new(Opts::new("keldurben_auth_attempts_total", "Login, register and import attempts by outcome"), &["kind", "result"])?;
+        let db_latency = HistogramVec::new(
+            HistogramOpts::new("keldurben_db_query_duration_seconds", "Latency of instrumented SQLite queries")
+                .buckets(vec![0.0005, 0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 1.0]),
+            &["op"])?;
+        let ws_handler_latency = HistogramVec::new(
+            HistogramOpts::new("keldurben_ws_handler_duration_seconds", "Time to handle one client message, including hub lock wait")
+                .buckets(vec![0.0001, 0.0005, 0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.5]),
+            &["type"])?;
+        registry.register(Box::new(ws_connections.clone()))?;
+        registry.register(Box::new(rooms.clone()))?;
+        registry.register(Box::new(messages_in.clone()))?;
+        registry.register(Box::new(messages_out.clone()))?;
+        registry.register(Box::new(broadcast_fanout.clone()))?;
+        registry.register(Box::new(auth_attempts.clone()))?;
+        registry.register(Box::new(db_latency.clone()))?;
+        registry.register(Box::new(ws_handler_latency.clone()))?;
+        Ok(Self { registry, ws_connections, rooms, messages_in, messages_out, broadcast_fanout, auth_attempts, db_latency, ws_handler_latency })
+    }
+}
+
+static METRICS: once_cell::sync::Lazy<Metrics> = once_cell::sync::Lazy::new(|| Metrics::new().expect("metric definitions are valid"));
+
+async fn metrics(State(app): State<AppState>) -> impl IntoResponse {
+    use prometheus::Encoder;
+    // Room gauges are a snapshot of the hub rather than tracked on every transition.
+    METRICS.rooms.reset();
+    {
+        let hub = app.hub.lock().await;
+        for room in hub.rooms.values() {
+            METRICS.rooms.with_label_values(&[room.game.as_str(), room.phase.as_str()]).inc();
+        }
+    }
+    let encoder = prometheus::TextEncoder::new();
+    let mut buf = Vec::new();
+    if encoder.encode(&METRICS.registry.gather(), &mut buf).is_err() {
+        return (StatusCode::INTERNAL_SERVER_ERROR, Json(serde_json::json!({"error":"metrics_error"}))).into_response();
+    }
+    ([(header::CONTENT_TYPE, encoder.format_type().to_string())], buf).into_response()
+}
+
+/// Buckets auth endpoint responses: 2xx is a success, 429 a rate-limited attempt, anything else a failure.
+async fn count_auth_attempt(req: axum::extract::Request, next: axum::middleware::Next) -> axum::response::Response {
+    let kind = match req.uri().path().rsplit('/').next() {
+        Some("login") => "login",
+        Some("register") => "register",
+        _ => "import_legacy",
+    };
+    let res = next.run(req).await;
+    let result = match res.status() {
+        s if s.is_success() => "success",
+        StatusCode::TOO_MANY_REQUESTS => "limited",
+        _ => "failure",
+    };
+    METRICS.auth_attempts.with_label_values(&[kind, result]).inc();
+    res
+}
+
+async fn timed<F: std::future::Future>(op: &'static str, fut: F) -> F::Output {
+    let started = Instant::now();
+    let out = fut.await;
+    METRICS.db_latency.with_label_values(&[op]).observe(started.elapsed().as_secs_f64());
+    out
+}
+
+// ===================== Auth rate limiting =====================
+/// Failures a key may rack up before lockouts start.
+const AUTH_FREE_FAILURES: u32 = 5;
+/// First lockout; each further failure doubles it.
+const AUTH_BASE_LOCKOUT: Duration = Duration::from_secs(2);
+const AUTH_MAX_LOCKOUT: Duration = Duration::from_secs(15 * 60);
+/// A key with no failures for this long starts from scratch.
+const AUTH_FORGET_AFTER: Duration = Duration::from_secs(30 * 60);
+
+/// Failure counters for login, register, legacy import and password re-checks, keyed by client IP and by username.
+#[derive(Default)]
+struct AuthLimiter {
+    entries: std::sync::Mutex<HashMap<String, AuthAttempts>>,
+}
+
+struct AuthAttempts { failures: u32, last_failure: Instant, locked_until: Option<Instant> }
+
+impl AuthLimiter {
+    /// Starts an attempt: refused with the longest lockout still running for any of `keys`, otherwise counted
+    /// as a failure straight away, so concurrent attempts cannot all slip in before the first one fails.
+    /// A successful attempt hands its count back with `refund`.
+    fn attempt(&self, keys: &[&str]) -> Result<(), Duration> {
+        self.attempt_at(keys, Instant::now())
+    }
+
+    fn attempt_at(&self, keys: &[&str], now: Instant) -> Result<(), Duration> {
+        let mut entries = self.entries.lock().unwrap();
+        let wait = keys.iter()
+            .filter_map(|k| entries.get(*k)?.locked_until)
+            .filter(|until| *until > now)
+            .map(|until| until - now)
+            .max();
+        if let Some(wait) = wait { return Err(wait); }
+        for key in keys {
+            let entry = entries.entry(key.to_string()).or_insert(AuthAttempts { failures: 0, last_failure: now, locked_until: None });
+            if now.duration_since(entry.last_failure) > AUTH_FORGET_AFTER { entry.failures = 0; }
+            entry.failures += 1;
+            entry.last_failure = now;
+            if entry.failures > AUTH_FREE_FAILURES {
+                let doublings = (entry.failures - AUTH_FREE_FAILURES - 1).min(16);
+                let lockout = AUTH_BASE_LOCKOUT.saturating_mul(1 << doublings).min(AUTH_MAX_LOCKOUT);
+                entry.locked_until = Some(now + lockout);
+            }
+        }
+        Ok(())
+    }
+
+    /// Takes back an attempt that succeeded, lifting the lockout it may have started.
+    fn refund(&self, keys: &[&str]) {
+        let mut entries = self.entries.lock().unwrap();
+        for key in keys {
+            if let Some(entry) = entries.get_mut(*key) {
+                entry.failures = entry.failures.saturating_sub(1);
+                if entry.failures <= AUTH_FREE_FAILURES { entry.locked_until = None; }
+            }
+        }
+    }
+
+    fn reset(&self, key: &str) {
+        self.entries.lock().unwrap().remove(key);
+    }
+
+    fn prune(&self) {
+        self.prune_at(Instant::now());
+    }
+
+    fn prune_at(&self, now: Instant) {
+        self.entries.lock().unwrap().retain(|_, e| {
+            now.duration_since(e.last_failure) <= AUTH_FORGET_AFTER || e.locked_until.is_some_and(|u| u > now)
+        });
+    }
+}
+
+fn too_many_attempts(wait: Duration) -> axum::response::Response {
+    let secs = wait.as_secs() + u64::from(wait.subsec_nanos() > 0);
+    (
+        StatusCode::TOO_MANY_REQUESTS,
+        [(header::RETRY_AFTER, secs.to_string())],
+        Json(serde_json::json!({"error":"too_many_attempts", "retry_after": secs})),
+    ).into_response()
+}
+
+/// The peer address, or behind a trusted proxy the right-most `X-Forwarded-For` entry that is not a trusted proxy.
+fn client_ip(cfg: &AppConfig, peer: SocketAddr, headers: &HeaderMap) -> IpAddr {
+    let peer_ip = peer.ip();
+    if !cfg.trusted_proxies.contains(&peer_ip) { return peer_ip; }
+    let forwarded = headers.get_all("x-forwarded-for").iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(','))
+        .filter_map(|hop| hop.trim().parse::<IpAddr>().ok())
+        .collect::<Vec<_>>();
+    forwarded.into_iter().rev()
+        .find(|ip| !cfg.trusted_proxies.contains(ip))
+        .unwrap_or(peer_ip)
+}
+
+// ===================== Auth utils =====================
+/// Runs argon2 work on the blocking pool: it is slow on purpose and would stall the async workers.
+async fn off_runtime<T: Send + 'static>(work: impl FnOnce() -> T + Send + 'static) -> T {
+    tokio::task::spawn_blocking(work).await.unwrap_or_else(|e| std::panic::resume_unwind(e.into_panic()))
+}
+
+async fn hash_password_async(params: &argon2::Params, password: &str) -> Result<String, argon2::password_hash::Error> {
+    let (params, password) = (params.clone(), password.to_string());
+    off_runtime(move || hash_password(&params, &password)).await
+}
+
+async fn verify_password_async(password: &str, pwd_hash: &str) -> bool {
+    let (password, pwd_hash) = (password.to_string(), pwd_hash.to_string());
+    off_runtime(move || verify_password(&password, &pwd_hash)).await
+}
+
+fn hash_password(params: &argon2::Params, password: &str) -> Result<String, argon2::password_hash::Error> {
+    use argon2::{Algorithm, Argon2, PasswordHasher, Version};
+    use argon2::password_hash::SaltString;
+    let salt = SaltString::generate(&mut rand::thread_rng());
+    let argon = Argon2::new(Algorithm::Argon2id, Version::V0x13, params.clone());
+    let hash = argon.hash_password(password.as_bytes(), &salt)?;
+    Ok(hash.to_string())
+}
+
+/// True when the stored hash is legacy, unparseable or cheaper than `policy` in any dimension.
+fn needs_rehash(policy: &argon2::Params, pwd_hash: &str) -> bool {
+    use argon2::password_hash::PasswordHash;
+    if is_legacy_hash(pwd_hash) { return true; }
+    let Ok(parsed) = PasswordHash::new(pwd_hash) else { return true };
+    if parsed.algorithm != argon2::Algorithm::Argon2id.ident() { return true; }
+    match argon2::Params::try_from(&parsed) {
+        Ok(stored) => stored.m_cost() < policy.m_cost() || stored.t_cost() < policy.t_cost() || stored.p_cost() < policy.p_cost(),
+        Err(_) => true,
+    }
+}
+
+/// Marks a `pwd_hash` carried over from the old client: unsalted hex SHA-256.
+const LEGACY_HASH_PREFIX: &str = "legacy-sha256$";
+
+fn is_legacy_hash(pwd_hash: &str) -> bool {
+    pwd_hash.starts_with(LEGACY_HASH_PREFIX)
+}
+
+fn verify_password(password: &str, pwd_hash: &str) -> bool {
+    use argon2::{Argon2, PasswordVerifier};
+    use argon2::password_hash::PasswordHash;
+    if let Some(expected) = pwd_hash.strip_prefix(LEGACY_HASH_PREFIX) {
+        use sha2::{Digest, Sha256};
+        let actual: String = Sha256::digest(password.as_bytes()).iter().map(|b| format!("{:02x}", b)).collect();
+        // Constant-time compare; both sides are 64 hex chars.
+        return actual.len() == expected.len()
+            && actual.bytes().zip(expected.bytes()).fold(0u8, |acc, (a, b)| acc | (a ^ b)) == 0;
+    }
+    match PasswordHash::new(pwd_hash) {
+        Ok(parsed) => Argon2::default().verify_password(password.as_bytes(), &parsed).is_ok(),
+        Err(_) => false,
+    }
+}
+
+fn issue_jwt(secret: &str, user_id: Uuid) -> String {
+    use jsonwebtoken::{encode, EncodingKey, Header};
+    use time::{Duration, OffsetDateTime};
+    let exp = (OffsetDateTime::now_utc() + Duration::days(30)).unix_timestamp() as usize;
+    let claims = serde_json::json!({ "sub": user_id.to_string(), "exp": exp });
+    encode(&Header::default(), &claims, &EncodingKey::from_secret(secret.as_bytes())).unwrap_or_default()
+}
+
+struct AuthBearer(String);
+#[axum::async_trait]
+impl<S> axum::extract::FromRequestParts<S> for AuthBearer
+where S: Send + Sync {
+    type Rejection = (StatusCode, String);
+    async fn from_request_parts(parts: &mut axum::http::request::Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        if let Some(val) = parts.headers.get(axum::http::header::AUTHORIZATION) {
+            if let Ok(s) = val.to_str() { if let Some(t) = s.strip_prefix("Bearer ") { return Ok(AuthBearer(t.to_string())); } }
+        }
+        Err((StatusCode::UNAUTHORIZED, "missing bearer".into()))
+    }
+}
+
+async fn auth_user(app: &AppState, token: &str) -> anyhow::Result<PublicUser> {
+    let row = auth_user_row(app, token).await?;
+    let uid = Uuid::parse_str(&row.id)?;
+    Ok(PublicUser::new(uid, row.username, row.avatar))
+}
+
+async fn auth_user_row(app: &AppState, token: &str) -> anyhow::Result<UserRow> {
+    use jsonwebtoken::{decode, DecodingKey, Validation, Algorithm};
+    let data = decode::<Claims>(token, &DecodingKey::from_secret(app.cfg.jwt_secret.as_bytes()), &Validation::new(Algorithm::HS256))?;
+    let uid = Uuid::parse_str(&data.claims.sub)?;
+    let row = timed("auth_user", sqlx::query_as::<_, UserRow>("SELECT id, username, pwd_hash, avatar FROM users WHERE id = ?1")
+        .bind(uid.to_string())
+        .fetch_one(&app.db)).await?;
+    Ok(row)
+}
+
+// ===================== Migration =====================
+struct Migration { version: i64, name: &'static str, sql: &'static str }
+
+/// Applied in order, each in its own transaction. Never edit a released entry;
+/// append a new one instead.
+const MIGRATIONS: &[Migration] = &[
+    // `IF NOT EXISTS` so databases from before versioning adopt it as v1.
+    Migration { version: 1, name: "users", sql: r#"
+        CREATE TABLE IF NOT EXISTS users (
+            id TEXT PRIMARY KEY,
+            username TEXT NOT NULL UNIQUE,
+            pwd_hash TEXT NOT NULL,
+            avatar TEXT NULL
+        );
+    "# },
+    // Finished games. `user_id` is NULL for guests and for accounts deleted after the fact.
+    Migration { version: 2, name: "match_history", sql: r#"
+        CREATE TABLE matches (
+            id TEXT PRIMARY KEY,
+            room TEXT NOT NULL,
+            started_at INTEGER NOT NULL,
+            ended_at INTEGER NULL
+        );
+        CREATE TABLE match_players (
+            match_id TEXT NOT NULL REFERENCES matches(id) ON DELETE CASCADE,
+            user_id TEXT NULL,
+            name TEXT NOT NULL,
+            score INTEGER NOT NULL DEFAULT 0
+        );
+        CREATE INDEX match_players_user ON match_players(user_id);
+    "# },
+    Migration { version: 3, name: "profiles", sql: r#"
+        ALTER TABLE users ADD COLUMN display_name TEXT NULL;
+        ALTER TABLE users ADD COLUMN bio TEXT NULL;
+        ALTER TABLE users ADD COLUMN rating INTEGER NOT NULL DEFAULT 1000;
+    "# },
+    Migration { version: 4, name: "user_settings", sql: r#"
+        CREATE TABLE user_settings (
+            user_id TEXT PRIMARY KEY REFERENCES users(id) ON DELETE CASCADE,
+            version INTEGER NOT NULL,
+            data TEXT NOT NULL,
+            updated_at INTEGER NOT NULL
+        );
+    "# },
+    // One row per ordered pair: `user_id` sent the request (pending/accepted) or placed the block.
+    Migration { version: 5, name: "friendships", sql: r#"
+        CREATE TABLE friendships (
+            user_id TEXT NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+            friend_id TEXT NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+            status TEXT NOT NULL CHECK (status IN ('pending', 'accepted', 'blocked')),
+            created_at INTEGER NOT NULL,
+            PRIMARY KEY (user_id, friend_id)
+        );
+        CREATE INDEX friendships_friend ON friendships(friend_id);
+    "# },
+    // Filled in by `backfill_username_norm`, which needs the Rust normaliser.
+    Migration { version: 6, name: "username_norm", sql: r#"
+        ALTER TABLE users ADD COLUMN username_norm TEXT NULL;
+        CREATE UNIQUE INDEX users_username_norm ON users(username_norm);
+    "# },
+    // Legacy localStorage accounts already brought over, so each is imported once.
+    Migration { version: 7, name: "legacy_imports", sql: r#"
+        CREATE TABLE legacy_imports (
+            legacy_username_norm TEXT PRIMARY KEY,
+            legacy_username TEXT NOT NULL,
+            user_id TEXT NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+            imported_at INTEGER NOT NULL
+        );
+    "# },
+    // Final place per player, so wins follow the standings' tie-break. Earlier rows are ranked by score.
+    Migration { version: 8, name: "match_places", sql: r#"
+        ALTER TABLE match_players ADD COLUMN place INTEGER NULL;
+        UPDATE match_players SET place = 1 + (
+            SELECT COUNT(*) FROM match_players o WHERE o.match_id = match_players.match_id AND o.score > match_players.score
+        );
+    "# },
+    // The rating added with profiles was never computed; it comes back when there is a rating system.
+    Migration { version: 9, name: "drop_rating", sql: r#"
+        ALTER TABLE users DROP COLUMN rating;
+    "# },
+    // Imports outlive the account they went to (`user_id` NULL), so a deleted account's legacy name stays taken.
+    Migration { version: 10, name: "legacy_import_tombstones", sql: r#"
+        CREATE TABLE legacy_imports_new (
+            legacy_username_norm TEXT PRIMARY KEY,
+            legacy_username TEXT NOT NULL,
+            user_id TEXT NULL REFERENCES users(id) ON DELETE SET NULL,
+            imported_at INTEGER NOT NULL
+        );
+        INSERT INTO legacy_imports_new SELECT legacy_username_norm, legacy_username, user_id, imported_at FROM legacy_imports;
+        DROP TABLE legacy_imports;
+        ALTER TABLE legacy_imports_new RENAME TO legacy_imports;
+    "# },
+];
+
+/// Brings the schema up to the newest version this build knows. Refuses to touch
+/// a database written by a newer build.
+pub async fn migrate(db: &SqlitePool) -> anyhow::Result<()> {
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS schema_version (
+            version INTEGER PRIMARY KEY,
+            name TEXT NOT NULL,
+            applied_at INTEGER NOT NULL
+        );
+        "#
+    ).execute(db).await?;
+    let current = schema_version(db).await?;
+    let latest = latest_schema_version();
+    if current > latest {
+        anyhow::bail!("database schema is at version {} but this build only knows up to {}; refusing to start", current, latest);
+    }
+    for m in MIGRATIONS.iter().filter(|m| m.version > current) {
+        let mut tx = db.begin().await?;
+        sqlx::raw_sql(m.sql).execute(&mut *tx).await
+            .map_err(|e| anyhow::anyhow!("migration {} ({}) failed: {}", m.version, m.name, e))?;
+        sqlx::query("INSERT INTO schema_version (version, name, applied_at) VALUES (?1, ?2, ?3)")
+            .bind(m.version)
+            .bind(m.name)
+            .bind(time::OffsetDateTime::now_utc().unix_timestamp())
+            .execute(&mut *tx).await?;
+        tx.commit().await?;
+        info!("applied migration {} ({})", m.version, m.name);
+    }
+    backfill_username_norm(db).await?;
+    Ok(())
+}
+
+fn latest_schema_version() -> i64 {
+    MIGRATIONS.last().map(|m| m.version).unwrap_or(0)
+}
+
+pub async fn schema_version(db: &SqlitePool) -> anyhow::Result<i64> {
+    let (version,): (Option<i64>,) = sqlx::query_as("SELECT MAX(version) FROM schema_version").fetch_one(db).await?;
+    Ok(version.unwrap_or(0))
+}
+
+/// Fills `username_norm` for accounts created before it existed, so every account has one.
+/// When two old names collide, the older account keeps its name and the other is renamed
+/// with a numeric suffix ("Ann" next to "ann" becomes "Ann_2"); each rename is logged.
+async fn backfill_username_norm(db: &SqlitePool) -> anyhow::Result<()> {
+    let rows: Vec<(String, String)> = sqlx::query_as("SELECT id, username FROM users WHERE username_norm IS NULL ORDER BY rowid")
+        .fetch_all(db).await?;
+    if rows.is_empty() { return Ok(()); }
+    let mut taken: HashSet<String> = sqlx::query_as::<_, (String,)>("SELECT username_norm FROM users WHERE username_norm IS NOT NULL")
+        .fetch_all(db).await?
+        .into_iter().map(|(n,)| n).collect();
+    // Renames must not take a name that a later row still holds as typed.
+    let pending: HashSet<String> = rows.iter().map(|(_, username)| normalize_username(username)).collect();
+    let mut tx = db.begin().await?;
+    for (id, username) in rows {
+        let mut name = username.clone();
+        let mut norm = normalize_username(&name);
+        if taken.contains(&norm) {
+            let mut n = 2;
+            loop {
+                name = format!("{}_{}", username, n);
+                norm = normalize_username(&name);
+                if !taken.contains(&norm) && !pending.contains(&norm) { break; }
+                n += 1;
+            }
+            tracing::warn!(target: "keldurben_server", event="username_norm_collision", user_id=%id, username=%username, renamed_to=%name);
+        }
+        taken.insert(norm.clone());
+        sqlx::query("UPDATE users SET username = ?1, username_norm = ?2 WHERE id = ?3").bind(name).bind(norm).bind(id).execute(&mut *tx).await?;
+    }
+    tx.commit().await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn test_db() -> SqlitePool {
+        let db = sqlx::sqlite::SqlitePoolOptions::new()
+            .max_connections(1)
+            .idle_timeout(None)
+            .max_lifetime(None)
+            .connect("sqlite::memory:")
+            .await
+            .unwrap();
+        migrate(&db).await.unwrap();
+        db
+    }
+
+    async fn add_user(db: &SqlitePool, username: &str) -> Uuid {
+        let id = Uuid::new_v4();
+        sqlx::query("INSERT INTO users (id, username, username_norm, pwd_hash) VALUES (?1, ?2, ?3, 'x')")
+            .bind(id.to_string()).bind(username).bind(normalize_username(username))
+            .execute(db).await.unwrap();
+        id
+    }
+
+    #[tokio::test]
+    async fn backfill_renames_colliding_old_accounts() {
+        let db = test_db().await;
+        let taken = add_user(&db, "bob").await;
+        for name in ["Ann", "ann", "ANN_2", "Bob"] {
+            sqlx::query("INSERT INTO users (id, username, pwd_hash) VALUES (?1, ?2, 'x')")
+                .bind(Uuid::new_v4().to_string()).bind(name).execute(&db).await.unwrap();
+        }
+        backfill_username_norm(&db).await.unwrap();
+        let names: Vec<(String, Option<String>)> = sqlx::query_as("SELECT username, username_norm FROM users ORDER BY rowid")
+            .fetch_all(&db).await.unwrap();
+        let expected = [("bob", "bob"), ("Ann", "ann"), ("ann_3", "ann_3"), ("ANN_2", "ann_2"), ("Bob_2", "bob_2")];
+        assert_eq!(names, expected.map(|(n, k)| (n.to_string(), Some(k.to_string()))));
+        let found = find_user_by_name(&db, "BOB").await.unwrap().unwrap();
+        assert_eq!(found.id, taken.to_string());
+    }
+
+    #[tokio::test]
+    async fn deleting_an_account_anonymises_its_matches() {
+        let db = test_db().await;
+        let (ann, bob) = (add_user(&db, "ann").await, add_user(&db, "bob").await);
+        let players = vec![
+            MatchPlayer { user_id: Some(ann), name: "Ann".into(), score: 3, place: 1 },
+            MatchPlayer { user_id: Some(bob), name: "Bob".into(), score: 1, place: 2 },
+        ];
+        record_match(&db, FinishedMatch { room: "r".into(), started_at: 1, ended_at: 2, players }).await.unwrap();
+
+        delete_user(&db, &ann.to_string()).await.unwrap();
+        let rows: Vec<(Option<String>, String, i32)> = sqlx::query_as("SELECT user_id, name, score FROM match_players ORDER BY score DESC")
+            .fetch_all(&db).await.unwrap();
+        assert_eq!(rows, vec![(None, DELETED_USER_NAME.to_string(), 3), (Some(bob.to_string()), "Bob".to_string(), 1)]);
+        let profile = load_profile(&db, bob).await.unwrap().unwrap();
+        assert_eq!((profile.games_played, profile.wins), (1, 0), "the other players' games still add up");
+    }
+
+    #[tokio::test]
+    async fn only_first_place_counts_as_a_win() {
+        let db = test_db().await;
+        let (ann, bob) = (add_user(&db, "ann").await, add_user(&db, "bob").await);
+        // Level on score, but Ann hit more bullseyes.
+        let players = vec![
+            MatchPlayer { user_id: Some(ann), name: "Ann".into(), score: 5, place: 1 },
+            MatchPlayer { user_id: Some(bob), name: "Bob".into(), score: 5, place: 2 },
+        ];
+        record_match(&db, FinishedMatch { room: "r".into(), started_at: 1, ended_at: 2, players }).await.unwrap();
+        let wins = |p: PublicProfile| (p.games_played, p.wins);
+        assert_eq!(wins(load_profile(&db, ann).await.unwrap().unwrap()), (1, 1));
+        assert_eq!(wins(load_profile(&db, bob).await.unwrap().unwrap()), (1, 0));
+    }
+
+    #[test]
+    fn settings_are_checked_field_by_field() {
+        assert_eq!(UserSettings::default().validate(), Ok(()));
+        let with = |f: fn(&mut UserSettings)| { let mut s = UserSettings::default(); f(&mut s); s.validate() };
+        assert_eq!(with(|s| s.version = SETTINGS_VERSION + 1), Err("unsupported_settings_version"));
+        assert_eq!(with(|s| s.language = "de".into()), Err("invalid_language"));
+        assert_eq!(with(|s| s.theme = "sepia".into()), Err("invalid_theme"));
+        assert_eq!(with(|s| s.sound_volume = 101), Err("invalid_sound_volume"));
+        assert_eq!(with(|s| s.sound_volume = 100), Ok(()));
+        assert_eq!(with(|s| s.default_nickname = Some("  ".into())), Err("invalid_default_nickname"));
+        assert_eq!(with(|s| s.default_nickname = Some("я".repeat(MAX_DISPLAY_NAME_CHARS + 1))), Err("invalid_default_nickname"));
+        assert_eq!(with(|s| s.default_nickname = Some("я".repeat(MAX_DISPLAY_NAME_CHARS))), Ok(()));
+        let partial: UserSettings = serde_json::from_str(r#"{"theme":"light"}"#).unwrap();
+        assert_eq!((partial.theme.as_str(), partial.language.as_str()), ("light", "ru"), "missing fields take their default");
+        assert!(serde_json::from_str::<UserSettings>(r#"{"colour":"red"}"#).is_err(), "unknown fields are refused");
+    }
+
+    #[test]
+    fn auth_lockouts_double_and_are_forgotten() {
+        let limiter = AuthLimiter::default();
+        let start = Instant::now();
+        // Attempts count as they start, so a burst gets no further than the free ones and one more.
+        for _ in 0..=AUTH_FREE_FAILURES { assert_eq!(limiter.attempt_at(&["ip", "user"], start), Ok(())); }
+        assert_eq!(limiter.attempt_at(&["ip"], start), Err(AUTH_BASE_LOCKOUT));
+        assert_eq!(limiter.attempt_at(&["elsewhere", "user"], start), Err(AUTH_BASE_LOCKOUT), "any locked key refuses");
+        let mut now = start + AUTH_BASE_LOCKOUT;
+        assert_eq!(limiter.attempt_at(&["user"], now), Ok(()), "a lockout runs out");
+        assert_eq!(limiter.attempt_at(&["user"], now), Err(AUTH_BASE_LOCKOUT * 2));
+        for _ in 0..20 {
+            now += AUTH_MAX_LOCKOUT;
+            limiter.attempt_at(&["user"], now).unwrap();
+        }
+        assert_eq!(limiter.attempt_at(&["user"], now), Err(AUTH_MAX_LOCKOUT));
+
+        // A successful attempt is handed back, with the lockout it started.
+        limiter.refund(&["ip"]);
+        assert_eq!(limiter.attempt_at(&["ip"], start), Ok(()));
+
+        // After a quiet spell the count starts over; pruning drops the keys nobody needs any more.
+        let later = now + AUTH_FORGET_AFTER + AUTH_MAX_LOCKOUT;
+        limiter.attempt_at(&["ip"], later).unwrap();
+        assert_eq!(limiter.attempt_at(&["ip"], later), Ok(()));
+        limiter.prune_at(later);
+        assert_eq!(limiter.entries.lock().unwrap().keys().collect::<Vec<_>>(), vec!["ip"]);
+        limiter.reset("ip");
+        assert!(limiter.entries.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn weaker_or_foreign_hashes_are_rehashed() {
+        let policy = argon2::Params::new(16, 2, 1, None).unwrap();
+        let weak = hash_password(&argon2::Params::new(8, 1, 1, None).unwrap(), "pw").unwrap();
+        let current = hash_password(&policy, "pw").unwrap();
+        assert!(needs_rehash(&policy, &format!("{}{}", LEGACY_HASH_PREFIX, "ab".repeat(32))));
+        assert!(needs_rehash(&policy, "not a hash"));
+        assert!(needs_rehash(&policy, &weak), "a cheaper cost than configured");
+        assert!(!needs_rehash(&policy, &current));
+        assert!(!needs_rehash(&argon2::Params::new(8, 1, 1, None).unwrap(), &current), "lowering the cost keeps stronger hashes");
+        assert!(verify_password("pw", &weak) && verify_password("pw", &current));
+    }
+
+    #[test]
+    fn usernames_fold_lookalikes_and_case() {
+        assert_eq!(normalize_username("Keldurben"), "keldurben");
+        // Cyrillic "а", "о" and "р" look Latin; digits that pass for letters fold too.
+        assert_eq!(normalize_username("Pаvel"), normalize_username("pavel"));
+        assert_eq!(normalize_username("ОРЕХ"), "opex");
+        assert_eq!(normalize_username("b0b-1.x"), "bob_l_x");
+    }
+
+    #[test]
+    fn username_policy_names_what_is_wrong() {
+        for ok in ["ann", "Олег_99", "x.y-z", &"a".repeat(USERNAME_MAX_CHARS)] {
+            assert_eq!(validate_username(ok), Ok(()), "{}", ok);
+        }
+        assert_eq!(validate_username("ab"), Err("username_too_short"));
+        assert_eq!(validate_username(&"a".repeat(USERNAME_MAX_CHARS + 1)), Err("username_too_long"));
+        assert_eq!(validate_username("ann smith"), Err("username_invalid_chars"));
+        assert_eq!(validate_username("ann😀"), Err("username_invalid_chars"));
+        assert_eq!(validate_username("_ann"), Err("username_invalid_start"));
+        assert_eq!(validate_username("Pаvel"), Err("username_mixed_scripts"));
+        assert_eq!(validate_username("R00T"), Err("username_reserved"));
+        assert_eq!(validate_username("аdmin"), Err("username_mixed_scripts"), "scripts are checked before the reserved list");
+    }
+
+    #[test]
+    fn message_ids_are_claimed_before_they_run() {
+        let mut recent = RecentReplies::default();
+        recent.reserve("a".into());
+        assert_eq!(recent.get("a"), Some(&None), "a copy arriving now finds the id taken");
+        recent.finish("a", Err("stale command".into()));
+        assert_eq!(recent.get("a"), Some(&Some(Err("stale command".into()))));
+        for i in 0..RecentReplies::KEEP { recent.reserve(i.to_string()); }
+        assert_eq!(recent.get("a"), None, "only the latest ids are kept");
+        recent.finish("a", Ok(()));
+        assert_eq!(recent.get("a"), Some(&Some(Ok(()))), "an answer outliving its claim is still kept");
+    }
+
+    fn png(width: u32, height: u32) -> Vec<u8> {
+        let mut out = std::io::Cursor::new(Vec::new());
+        image::RgbImage::new(width, height).write_to(&mut out, image::ImageFormat::Png).unwrap();
+        out.into_inner()
+    }
+
+    #[test]
+    fn avatars_are_refused_unless_they_decode_within_limits() {
+        assert_eq!(reencode_avatar(b"just some text"), Err("unsupported_image"));
+        assert_eq!(reencode_avatar(b"BM\x36\x00\x00\x00\x00\x00\x00\x00\x36\x00\x00\x00"), Err("unsupported_image"), "BMP is not on the list");
+        let mut truncated = png(16, 16);
+        truncated.truncate(40);
+        assert_eq!(reencode_avatar(&truncated), Err("invalid_image"));
+        assert_eq!(reencode_avatar(&png(MAX_AVATAR_SOURCE_EDGE + 1, 1)), Err("image_too_large"));
+    }
+
+    #[test]
+    fn avatars_are_redrawn_without_what_was_smuggled_along() {
+        let mut upload = png(40, 20);
+        upload.extend_from_slice(b"<script>alert(1)</script>");
+        let stored = reencode_avatar(&upload).unwrap();
+        assert!(!stored.windows(8).any(|w| w == b"<script>"));
+        let img = image::load_from_memory_with_format(&stored, image::ImageFormat::Png).unwrap();
+        assert_eq!((img.width(), img.height()), (AVATAR_SIZE, AVATAR_SIZE));
+    }
+
+    #[test]
+    fn generated_avatars_are_stable_and_escaped() {
+        assert_eq!(initials_avatar_svg("anna_smith"), initials_avatar_svg("anna_smith"));
+        assert_ne!(initials_avatar_svg("anna_smith"), initials_avatar_svg("anna_smyth"), "another name, another colour");
+        assert!(initials_avatar_svg("anna_smith").contains(">AS</text>"));
+        assert!(initials_avatar_svg("олег").contains(">ОЛ</text>"));
+        assert!(initials_avatar_svg("__").contains(">?</text>"));
+        let svg = initials_avatar_svg("<&\"> x");
+        assert!(svg.contains(">&lt;X</text>"), "{}", svg);
+        let svg = initials_avatar_svg("&\"");
+        assert!(svg.contains(">&amp;&quot;</text>"), "{}", svg);
+        assert_eq!(svg.matches('<').count(), initials_avatar_svg("ab").matches('<').count(), "no markup gets through");
+    }
+
+    #[test]
+    fn log_files_rotate_at_the_limit_and_keep_only_so_many() {
+        use std::io::Write;
+        let dir = std::env::temp_dir().join(format!("keldurben-log-{}", Uuid::new_v4()));
+        let path = dir.join("server.log");
+        let mut log = RotatingFile::open(path.clone(), 10, 2).unwrap();
+        let read = |p: std::path::PathBuf| std::fs::read_to_string(p).unwrap_or_default();
+
+        log.write_all(b"aaaa\n").unwrap();
+        log.write_all(b"bbbb\n").unwrap();
+        assert_eq!(read(path.clone()), "aaaa\nbbbb\n", "exactly at the limit stays in one file");
+        assert!(!log.rotated(1).exists());
+
+        log.write_all(b"cccc\n").unwrap();
+        assert_eq!(read(path.clone()), "cccc\n");
+        assert_eq!(read(log.rotated(1)), "aaaa\nbbbb\n");
+
+        log.write_all(b"dddddddd\n").unwrap();
+        log.write_all(b"eeeeeeeeeeee\n").unwrap();
+        assert_eq!(read(path.clone()), "eeeeeeeeeeee\n", "a line longer than the limit still gets written whole");
+        assert_eq!(read(log.rotated(1)), "dddddddd\n");
+        assert_eq!(read(log.rotated(2)), "cccc\n");
+        assert!(!log.rotated(3).exists(), "only two old files are kept");
+
+        drop(log);
+        let mut log = RotatingFile::open(path.clone(), 10, 2).unwrap();
+        log.write_all(b"f\n").unwrap();
+        assert_eq!(read(path.clone()), "f\n", "a reopened file counts what it already holds");
+        assert_eq!(read(log.rotated(1)), "eeeeeeeeeeee\n");
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn empty_private_rooms_are_dropped() {
+        let mut hub = WsHub::default();
+        let (host, bot) = (Uuid::new_v4(), Uuid::new_v4());
+        join_room(&mut hub, host, "host".into(), "secret".into(), true, None).unwrap();
+        hub.bots.insert(bot, bots::Bot::new(BotDifficulty::Easy, Lang::En));
+        join_room(&mut hub, bot, "bot".into(), "secret".into(), false, None).unwrap();
+        join_room(&mut hub, Uuid::new_v4(), "guest".into(), "open".into(), false, None).unwrap();
+        hub.invites.insert(Uuid::new_v4(), RoomInvite { from: host, to: Uuid::new_v4(), room: "secret".into(), created: std::time::Instant::now() });
+
+        leave_room(&mut hub, host);
+        assert!(!hub.rooms.contains_key("secret"));
+        assert!(hub.invites.is_empty() && hub.bots.is_empty() && !hub.conns.contains_key(&bot));
+        assert!(hub.rooms.contains_key("open"), "public rooms are left alone");
+    }
+}
@@ -0,0 +1,111 @@
+//! How a guess is scored at reveal. A room plays one [`Scoring`] preset, chosen by its host.
+
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::{board::Board, color::delta_e};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum Scoring {
+    /// 3/2/1 points for grid distance 0/1/2 counted in steps along rows and columns.
+    #[default]
+    Manhattan,
+    /// The tabletop rules: 3 on the target, 2 inside the 3x3 frame around it, 1 just outside it;
+    /// the cue giver gets a point for every guess inside the frame.
+    Frame,
+    /// Perceptual: points by ΔE between the guessed and the target colour, whatever their places on the grid.
+    /// The cue giver gets a point for every guess that scores 2 or more.
+    DeltaE,
+}
+
+/// How one guess did.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) struct Outcome {
+    /// Grid steps, or ΔE for [`Scoring::DeltaE`].
+    pub distance: f32,
+    pub points: i32,
+    /// Earns the cue giver a point.
+    pub giver_point: bool,
+}
+
+/// ΔE up to which a guess earns 3, 2 and 1 points: a barely visible difference, then roughly one and two cells away.
+const DELTA_E_STEPS: [f32; 3] = [3.0, 15.0, 30.0];
+
+impl Scoring {
+    pub(crate) fn score(self, board: &Board, guess: usize, target: usize) -> Outcome {
+        let cols = board.cols as usize;
+        let (dr, dc) = ((guess / cols).abs_diff(target / cols), (guess % cols).abs_diff(target % cols));
+        let by_steps = |d: usize| match d { 0 => 3, 1 => 2, 2 => 1, _ => 0 };
+        match self {
+            Scoring::Manhattan => {
+                let d = dr + dc;
+                Outcome { distance: d as f32, points: by_steps(d), giver_point: false }
+            }
+            Scoring::Frame => {
+                let d = dr.max(dc);
+                Outcome { distance: d as f32, points: by_steps(d), giver_point: d <= 1 }
+            }
+            Scoring::DeltaE => {
+                let d = delta_e(board.lab(guess), board.lab(target));
+                let points = 3 - DELTA_E_STEPS.iter().filter(|step| d > **step).count() as i32;
+                Outcome { distance: (d * 10.0).round() / 10.0, points, giver_point: points >= 2 }
+            }
+        }
+    }
+}
+
+/// Which of a guesser's two guesses was scored: the second one if they made it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum GuessUsed { First, Second }
+
+/// How one player did in the last revealed round. The guess fields are empty for the cue giver
+/// and for guessers who never guessed.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PlayerRound {
+    pub player: Uuid,
+    pub guess: Option<usize>,
+    pub guess_used: Option<GuessUsed>,
+    /// In the units of the round's scoring (see [`Outcome::distance`]).
+    pub distance: Option<f32>,
+    pub points: i32,
+    /// Total after this round.
+    pub score: i32,
+}
+
+/// The outcome of the last revealed round; the cue giver is listed among the players too.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RoundResult {
+    pub round: u32,
+    pub scoring: Scoring,
+    pub target: usize,
+    pub cue_giver: Uuid,
+    pub cue_giver_points: i32,
+    pub players: Vec<PlayerRound>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn frame_counts_diagonals_as_adjacent() {
+        let board = Board::CLASSIC;
+        let target = 5 * 30 + 5;
+        let diagonal = target + 30 + 1;
+        assert_eq!(Scoring::Frame.score(&board, diagonal, target).points, 2);
+        assert!(Scoring::Frame.score(&board, diagonal, target).giver_point);
+        assert_eq!(Scoring::Manhattan.score(&board, diagonal, target).points, 1);
+        assert_eq!(Scoring::Frame.score(&board, target + 2, target), Outcome { distance: 2.0, points: 1, giver_point: false });
+    }
+
+    #[test]
+    fn delta_e_follows_the_colours() {
+        let board = Board::CLASSIC;
+        let target = 5 * 30 + 20;
+        assert_eq!(Scoring::DeltaE.score(&board, target, target).points, 3);
+        assert_eq!(Scoring::DeltaE.score(&board, target + 30, target).points, 2);
+        assert_eq!(Scoring::DeltaE.score(&board, target - 15, target).points, 0);
+    }
+}
@@ -0,0 +1,760 @@
+//! End-to-end: a real server on an ephemeral port with in-memory SQLite, driven by WebSocket clients.
+
+use std::{net::SocketAddr, time::Duration};
+
+use futures_util::{SinkExt, StreamExt};
+use keldurben_server::{build_router, metrics_router, migrate, AppConfig, AppState, ClientEnvelope, ClientMsg, GameLength, GameStateDto, GuessUsed, ServerMsg};
+use sqlx::{sqlite::SqlitePoolOptions, SqlitePool};
+use tokio::{io::{AsyncReadExt, AsyncWriteExt}, net::{TcpListener, TcpStream}};
+use tokio_tungstenite::{tungstenite::Message, MaybeTlsStream, WebSocketStream};
+use uuid::Uuid;
+
+const RECV_TIMEOUT: Duration = Duration::from_secs(5);
+
+async fn start_server() -> SocketAddr {
+    start_server_with(false).await.0
+}
+
+/// Also returns the pool, so a test can take the database away, and with `separate_metrics`
+/// the address of a `METRICS_BIND` listener serving [`metrics_router`].
+async fn start_server_with(separate_metrics: bool) -> (SocketAddr, Option<SocketAddr>, SqlitePool) {
+    // One connection that never expires: each in-memory SQLite connection is its own database.
+    let db = SqlitePoolOptions::new()
+        .max_connections(1)
+        .idle_timeout(None)
+        .max_lifetime(None)
+        .connect("sqlite::memory:")
+        .await
+        .unwrap();
+    migrate(&db).await.unwrap();
+
+    let data_dir = std::env::temp_dir().join(format!("keldurben-test-{}", Uuid::new_v4()));
+    // The operator's export of old client accounts; "olga" used the password "old-secret".
+    std::fs::create_dir_all(&data_dir).unwrap();
+    std::fs::write(data_dir.join("legacy-accounts.json"), r#"[{"username":"olga","passwordHash":"5d865deae06fbd34fe9ce848f3e5fc4368f2f612b18aef47f29f2164563a0140"}]"#).unwrap();
+    let cfg = AppConfig {
+        bind_addr: "127.0.0.1:0".parse().unwrap(),
+        jwt_secret: "test-secret".into(),
+        admin_secret: "test-admin".into(),
+        static_dir: data_dir.join("static").to_string_lossy().into_owned(),
+        data_dir: data_dir.to_string_lossy().into_owned(),
+        trusted_proxies: Vec::new(),
+        argon2_params: argon2::Params::new(8, 1, 1, None).unwrap(),
+        metrics_bind: None,
+    };
+    let metrics_listener = match separate_metrics {
+        true => Some(TcpListener::bind("127.0.0.1:0").await.unwrap()),
+        false => None,
+    };
+    let metrics_addr = metrics_listener.as_ref().map(|l| l.local_addr().unwrap());
+    let cfg = AppConfig { metrics_bind: metrics_addr, ..cfg };
+    let state = AppState::new(cfg, db.clone()).await.unwrap();
+    if let Some(listener) = metrics_listener {
+        let app = metrics_router(state.clone());
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+    }
+
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        axum::serve(listener, build_router(state).into_make_service_with_connect_info::<SocketAddr>()).await.unwrap();
+    });
+    (addr, metrics_addr, db)
+}
+
+/// One HTTP/1.0 REST call, so the body is neither chunked nor kept alive; returns the status and the JSON body (`null` when there is none).
+async fn http(addr: SocketAddr, method: &str, path: &str, token: Option<&str>, body: Option<serde_json::Value>) -> (u16, serde_json::Value) {
+    let body = body.map(|b| b.to_string()).unwrap_or_default();
+    http_raw(addr, method, path, token, "application/json", body.as_bytes()).await
+}
+
+async fn http_raw(addr: SocketAddr, method: &str, path: &str, token: Option<&str>, content_type: &str, body: &[u8]) -> (u16, serde_json::Value) {
+    let (status, body) = http_text(addr, method, path, token, content_type, body).await;
+    // Bodies that are not JSON (pictures, metrics) come back as `null`.
+    (status, serde_json::from_str(&body).unwrap_or(serde_json::Value::Null))
+}
+
+async fn http_text(addr: SocketAddr, method: &str, path: &str, token: Option<&str>, content_type: &str, body: &[u8]) -> (u16, String) {
+    let auth = token.map(|t| format!("Authorization: Bearer {}\r\n", t)).unwrap_or_default();
+    let head = format!(
+        "{} {} HTTP/1.0\r\nHost: {}\r\n{}Content-Type: {}\r\nContent-Length: {}\r\n\r\n",
+        method, path, addr, auth, content_type, body.len(),
+    );
+    let mut stream = TcpStream::connect(addr).await.unwrap();
+    stream.write_all(head.as_bytes()).await.unwrap();
+    stream.write_all(body).await.unwrap();
+    let mut response = Vec::new();
+    stream.read_to_end(&mut response).await.unwrap();
+    let response = String::from_utf8_lossy(&response);
+    let (head, body) = response.split_once("\r\n\r\n").unwrap();
+    let status = head.split_whitespace().nth(1).unwrap().parse().unwrap();
+    (status, body.to_string())
+}
+
+/// Registers an account and returns its token and id.
+async fn register(addr: SocketAddr, username: &str) -> (String, Uuid) {
+    let (status, body) = http(addr, "POST", "/api/auth/register", None, Some(serde_json::json!({"username": username, "password": "hunter22"}))).await;
+    assert_eq!(status, 200, "{}", body);
+    (body["token"].as_str().unwrap().to_string(), body["user"]["id"].as_str().unwrap().parse().unwrap())
+}
+
+struct Client {
+    name: &'static str,
+    id: Uuid,
+    /// Sent with numbered messages, like a browser tab's session id.
+    session: String,
+    ws: WebSocketStream<MaybeTlsStream<TcpStream>>,
+}
+
+impl Client {
+    async fn connect(addr: SocketAddr, name: &'static str) -> Self {
+        let (ws, _) = tokio_tungstenite::connect_async(format!("ws://{}/ws", addr)).await.unwrap();
+        Self { name, id: Uuid::nil(), session: Uuid::new_v4().to_string(), ws }
+    }
+
+    async fn send(&mut self, msg: ClientMsg) {
+        self.ws.send(Message::Text(serde_json::to_string(&msg).unwrap())).await.unwrap();
+    }
+
+    /// Sends `msg` numbered `msg_id`, meant for the given round.
+    async fn send_numbered(&mut self, msg_id: &str, expect_round: Option<u32>, msg: ClientMsg) {
+        let envelope = ClientEnvelope { msg_id: Some(msg_id.into()), session: Some(self.session.clone()), expect_round, expect_phase: None, msg };
+        self.ws.send(Message::Text(serde_json::to_string(&envelope).unwrap())).await.unwrap();
+    }
+
+    async fn reply(&mut self) -> Result<String, (String, String)> {
+        match self.recv().await {
+            ServerMsg::Ack { msg_id } => Ok(msg_id),
+            ServerMsg::Reject { msg_id, reason } => Err((msg_id, reason)),
+            other => panic!("{}: expected ack or reject, got {:?}", self.name, other),
+        }
+    }
+
+    async fn recv(&mut self) -> ServerMsg {
+        loop {
+            let msg = match tokio::time::timeout(RECV_TIMEOUT, self.ws.next()).await {
+                Ok(Some(msg)) => msg.unwrap(),
+                Ok(None) => panic!("{}: connection closed", self.name),
+                Err(_) => panic!("{}: timed out waiting for a message", self.name),
+            };
+            if let Message::Text(text) = msg {
+                return serde_json::from_str(&text).unwrap_or_else(|e| panic!("{}: bad server message {}: {}", self.name, text, e));
+            }
+        }
+    }
+
+    async fn state(&mut self) -> GameStateDto {
+        match self.recv().await {
+            ServerMsg::State { state } => *state,
+            other => panic!("{}: expected state, got {:?}", self.name, other),
+        }
+    }
+
+    async fn join(&mut self, room: &str) -> GameStateDto {
+        self.join_as(room, None).await
+    }
+
+    /// Joins signed in to the account `token` belongs to.
+    async fn join_as(&mut self, room: &str, token: Option<&str>) -> GameStateDto {
+        self.send(ClientMsg::Join { name: self.name.into(), room: Some(room.into()), token: token.map(String::from), private: None, board: None }).await;
+        loop {
+            match self.recv().await {
+                ServerMsg::Welcome { id, room: joined } => {
+                    assert_eq!(joined, room);
+                    self.id = id;
+                    break;
+                }
+                // Signed-in connections hear about their friends first.
+                ServerMsg::Presence { .. } => {}
+                other => panic!("{}: expected welcome, got {:?}", self.name, other),
+            }
+        }
+        self.state().await
+    }
+
+    async fn assert_quiet(&mut self) {
+        if let Ok(Some(msg)) = tokio::time::timeout(Duration::from_millis(100), self.ws.next()).await {
+            panic!("{}: unexpected message {:?}", self.name, msg);
+        }
+    }
+}
+
+/// Reads the next message of every client, which must be the same room state.
+async fn next_states(clients: &mut [Client]) -> GameStateDto {
+    let mut states = Vec::new();
+    for c in clients.iter_mut() {
+        states.push(c.state().await);
+    }
+    let first = states.remove(0);
+    for s in &states {
+        assert_eq!((&s.phase, s.round, s.cue_giver), (&first.phase, first.round, first.cue_giver));
+        assert_eq!(s.players.iter().map(|p| (p.id, p.score)).collect::<Vec<_>>(), first.players.iter().map(|p| (p.id, p.score)).collect::<Vec<_>>());
+    }
+    first
+}
+
+fn score_of(state: &GameStateDto, id: Uuid) -> i32 {
+    state.players.iter().find(|p| p.id == id).map(|p| p.score).unwrap()
+}
+
+#[tokio::test]
+async fn full_game_with_three_players() {
+    let addr = start_server().await;
+    let room = "e2e";
+    let mut clients = vec![
+        Client::connect(addr, "Ann").await,
+        Client::connect(addr, "Bob").await,
+        Client::connect(addr, "Cid").await,
+    ];
+
+    // Joining: the newcomer gets welcome + state, everyone already seated gets the state.
+    for i in 0..clients.len() {
+        let state = clients[i].join(room).await;
+        assert_eq!(state.phase, "lobby");
+        assert_eq!(state.players.len(), i + 1);
+        for c in clients[..i].iter_mut() {
+            assert_eq!(c.state().await.players.len(), i + 1);
+        }
+    }
+
+    // Nobody may start until everyone is ready.
+    clients[0].send(ClientMsg::StartGame { force: false }).await;
+    match clients[0].recv().await {
+        ServerMsg::Error { message } => assert_eq!(message, "not everyone is ready"),
+        other => panic!("expected error, got {:?}", other),
+    }
+    for i in 0..clients.len() {
+        clients[i].send(ClientMsg::SetReady { ready: true }).await;
+        let state = next_states(&mut clients).await;
+        assert_eq!(state.start_blockers.is_empty(), i + 1 == clients.len());
+    }
+
+    clients[0].send(ClientMsg::StartGame { force: false }).await;
+    let mut state = next_states(&mut clients).await;
+    assert_eq!((state.phase.as_str(), state.round), ("cue1", 1));
+
+    // Every player gives the cue once; each guesser hits the target exactly twice.
+    for round in 1..=3u32 {
+        let giver_id = state.cue_giver.unwrap();
+        assert_eq!(giver_id, clients[(round - 1) as usize].id, "cue giver rotates in join order");
+        let giver = clients.iter().position(|c| c.id == giver_id).unwrap();
+        let guessers: Vec<usize> = (0..clients.len()).filter(|&i| i != giver).collect();
+        let before: Vec<i32> = clients.iter().map(|c| score_of(&state, c.id)).collect();
+
+        let options = state.select_options.clone().expect("target options offered at cue1");
+        assert_eq!(options.len(), 4);
+        let target = options[0];
+        clients[giver].send(ClientMsg::ChooseTarget { index: target }).await;
+        state = next_states(&mut clients).await;
+        assert!(state.select_options.is_none());
+        assert!(state.target.is_none(), "target stays hidden until reveal");
+
+        clients[giver].send(ClientMsg::LockCue1 { cue: "sky".into() }).await;
+        state = next_states(&mut clients).await;
+        assert_eq!((state.phase.as_str(), state.cue1.as_deref()), ("guess1", Some("sky")));
+
+        for (n, &g) in guessers.iter().enumerate() {
+            clients[g].send(ClientMsg::Guess { cell: target }).await;
+            state = next_states(&mut clients).await;
+            assert_eq!(state.phase, if n + 1 == guessers.len() { "cue2" } else { "guess1" });
+        }
+
+        clients[giver].send(ClientMsg::LockCue2 { cue2: "clear sky".into() }).await;
+        state = next_states(&mut clients).await;
+        assert_eq!((state.phase.as_str(), state.cue2.as_deref()), ("guess2", Some("clear sky")));
+
+        for (n, &g) in guessers.iter().enumerate() {
+            clients[g].send(ClientMsg::Guess { cell: target }).await;
+            state = next_states(&mut clients).await;
+            assert_eq!(state.phase, if n + 1 == guessers.len() { "reveal" } else { "guess2" });
+        }
+        assert_eq!(state.target, Some(target));
+        let result = state.round_result.clone().expect("round result at reveal");
+        assert_eq!((result.round, result.target, result.cue_giver, result.cue_giver_points), (round, target, giver_id, 0));
+        for (i, c) in clients.iter().enumerate() {
+            let gained = score_of(&state, c.id) - before[i];
+            assert_eq!(gained, if i == giver { 0 } else { 3 }, "{} in round {}", c.name, round);
+            let entry = result.players.iter().find(|p| p.player == c.id).unwrap();
+            assert_eq!((entry.points, entry.score), (gained, score_of(&state, c.id)));
+            if i != giver {
+                assert_eq!((entry.guess, entry.guess_used, entry.distance), (Some(target), Some(GuessUsed::Second), Some(0.0)));
+            }
+        }
+
+        if round < 3 {
+            clients[giver].send(ClientMsg::NextRound).await;
+            state = next_states(&mut clients).await;
+            assert_eq!((state.phase.as_str(), state.round), ("cue1", round + 1));
+            assert!(state.round_result.is_none());
+        }
+    }
+
+    for c in clients.iter() {
+        assert_eq!(score_of(&state, c.id), 6, "{}", c.name);
+    }
+    for c in clients.iter_mut() {
+        c.assert_quiet().await;
+    }
+}
+
+#[tokio::test]
+async fn numbered_commands_are_acknowledged_once() {
+    let addr = start_server().await;
+    let mut clients = vec![Client::connect(addr, "Ann").await, Client::connect(addr, "Bob").await];
+    clients[0].join("acks").await;
+    clients[1].join("acks").await;
+    clients[0].state().await;
+    for i in 0..2 {
+        clients[i].send(ClientMsg::SetReady { ready: true }).await;
+        next_states(&mut clients).await;
+    }
+
+    clients[0].send_numbered("start", None, ClientMsg::StartGame { force: false }).await;
+    let state = next_states(&mut clients).await;
+    assert_eq!(clients[0].reply().await, Ok("start".into()));
+    // A retry is answered the same way but not carried out again.
+    clients[0].send_numbered("start", None, ClientMsg::StartGame { force: false }).await;
+    assert_eq!(clients[0].reply().await, Ok("start".into()));
+    clients[1].assert_quiet().await;
+
+    let target = state.select_options.unwrap()[0];
+    clients[0].send_numbered("target", Some(1), ClientMsg::ChooseTarget { index: target }).await;
+    next_states(&mut clients).await;
+    assert_eq!(clients[0].reply().await, Ok("target".into()));
+    clients[0].send_numbered("bad cue", Some(1), ClientMsg::LockCue1 { cue: "blue".into() }).await;
+    assert_eq!(clients[0].reply().await, Err(("bad cue".into(), "cues may not name a colour".into())));
+    for (who, msg) in [(0, ClientMsg::LockCue1 { cue: "sky".into() }), (1, ClientMsg::Guess { cell: target }), (0, ClientMsg::LockCue2 { cue2: "night".into() }), (1, ClientMsg::Guess { cell: target })] {
+        clients[who].send(msg).await;
+        next_states(&mut clients).await;
+    }
+
+    // A double click on "next round": the second click was meant for round 1, which is over.
+    clients[1].send_numbered("next-1", Some(1), ClientMsg::NextRound).await;
+    clients[1].send_numbered("next-2", Some(1), ClientMsg::NextRound).await;
+    let state = next_states(&mut clients).await;
+    assert_eq!(clients[1].reply().await, Ok("next-1".into()));
+    assert_eq!(clients[1].reply().await, Err(("next-2".into(), "stale command".into())));
+    assert_eq!((state.round, state.cue_giver), (2, Some(clients[1].id)));
+    for c in clients.iter_mut() {
+        c.assert_quiet().await;
+    }
+}
+
+/// Plays a one-rotation game in which every guess hits the target, so everyone ties for first.
+async fn play_short_game(clients: &mut [Client]) -> GameStateDto {
+    clients[0].send(ClientMsg::SetGameLength { length: GameLength { rotations: 1, score_target: 0 } }).await;
+    next_states(clients).await;
+    for i in 0..clients.len() {
+        clients[i].send(ClientMsg::SetReady { ready: true }).await;
+        next_states(clients).await;
+    }
+    clients[0].send(ClientMsg::StartGame { force: false }).await;
+    let mut state = next_states(clients).await;
+    loop {
+        let giver = clients.iter().position(|c| Some(c.id) == state.cue_giver).unwrap();
+        let target = state.select_options.clone().unwrap()[0];
+        let mut steps = vec![(giver, ClientMsg::ChooseTarget { index: target }), (giver, ClientMsg::LockCue1 { cue: "sky".into() })];
+        steps.extend((0..clients.len()).filter(|&i| i != giver).map(|i| (i, ClientMsg::Guess { cell: target })));
+        steps.push((giver, ClientMsg::LockCue2 { cue2: "night".into() }));
+        steps.extend((0..clients.len()).filter(|&i| i != giver).map(|i| (i, ClientMsg::Guess { cell: target })));
+        for (who, msg) in steps {
+            clients[who].send(msg).await;
+            state = next_states(clients).await;
+        }
+        if state.phase == "game_over" { return state; }
+        clients[giver].send(ClientMsg::NextRound).await;
+        state = next_states(clients).await;
+    }
+}
+
+#[tokio::test]
+async fn finished_games_count_on_the_profile() {
+    let addr = start_server().await;
+    let (token, user_id) = register(addr, "ann").await;
+    let mut clients = vec![Client::connect(addr, "Ann").await, Client::connect(addr, "Bob").await];
+    clients[0].join_as("history", Some(&token)).await;
+    clients[1].join("history").await;
+    clients[0].state().await;
+
+    let state = play_short_game(&mut clients).await;
+    assert_eq!(state.players.iter().map(|p| p.score).collect::<Vec<_>>(), vec![3, 3]);
+
+    // The match is written after the broadcast, so give the writer a moment.
+    let mut profile = serde_json::Value::Null;
+    for _ in 0..50 {
+        profile = http(addr, "GET", &format!("/api/users/{}", user_id), None, None).await.1;
+        if profile["games_played"] == 1 { break; }
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+    assert_eq!((profile["games_played"].as_i64(), profile["wins"].as_i64()), (Some(1), Some(1)), "{}", profile);
+}
+
+#[tokio::test]
+async fn legacy_import_needs_the_old_password() {
+    let addr = start_server().await;
+    let (token, _) = register(addr, "mallory").await;
+    let import = |password: &str| Some(serde_json::json!({"username": "Olga", "password": password}));
+
+    // Neither a stranger nor a signed-in player can claim the name without the password.
+    assert_eq!(http(addr, "POST", "/api/auth/import-legacy", None, import("guess")).await.0, 401);
+    assert_eq!(http(addr, "POST", "/api/auth/import-legacy", Some(&token), import("guess")).await.0, 401);
+    let unknown = Some(serde_json::json!({"username": "nobody", "password": "old-secret"}));
+    assert_eq!(http(addr, "POST", "/api/auth/import-legacy", None, unknown).await.0, 401);
+
+    let (status, body) = http(addr, "POST", "/api/auth/import-legacy", None, import("old-secret")).await;
+    assert_eq!((status, body["user"]["username"].as_str()), (201, Some("olga")), "{}", body);
+    assert_eq!(http(addr, "POST", "/api/auth/import-legacy", Some(&token), import("old-secret")).await.0, 409);
+    let login = Some(serde_json::json!({"username": "olga", "password": "old-secret"}));
+    assert_eq!(http(addr, "POST", "/api/auth/login", None, login).await.0, 200);
+}
+
+
+#[tokio::test]
+async fn password_rechecks_share_the_login_lockout() {
+    let addr = start_server().await;
+    let (token, _) = register(addr, "vera").await;
+    let wrong = || Some(serde_json::json!({"old_password": "nope", "new_password": "whatever"}));
+    for _ in 0..6 {
+        assert_eq!(http(addr, "PUT", "/api/me/password", Some(&token), wrong()).await.0, 403);
+    }
+    assert_eq!(http(addr, "PUT", "/api/me/password", Some(&token), wrong()).await.0, 429);
+    // The same key guards account deletion and login, even with the right password.
+    assert_eq!(http(addr, "DELETE", "/api/me", Some(&token), Some(serde_json::json!({"password": "hunter22"}))).await.0, 429);
+    assert_eq!(http(addr, "POST", "/api/auth/login", None, Some(serde_json::json!({"username": "vera", "password": "hunter22"}))).await.0, 429);
+}
+
+#[tokio::test]
+async fn a_resend_after_reconnecting_is_not_carried_out_again() {
+    let addr = start_server().await;
+    let mut clients = vec![Client::connect(addr, "Ann").await, Client::connect(addr, "Bob").await];
+    clients[0].join("retry").await;
+    clients[1].join("retry").await;
+    clients[0].state().await;
+    for i in 0..2 {
+        clients[i].send(ClientMsg::SetReady { ready: true }).await;
+        next_states(&mut clients).await;
+    }
+    clients[0].send_numbered("start", None, ClientMsg::StartGame { force: false }).await;
+    next_states(&mut clients).await;
+    assert_eq!(clients[0].reply().await, Ok("start".into()));
+
+    // Ann's connection drops before she learns the outcome; she reconnects, rejoins and resends.
+    let session = clients.remove(0).session;
+    assert_eq!(clients[0].state().await.players.len(), 1);
+    let mut ann = Client::connect(addr, "Ann").await;
+    ann.session = session;
+    ann.join("retry").await;
+    let state = clients[0].state().await;
+    assert_eq!((state.phase.as_str(), state.round), ("cue1", 1));
+    ann.send_numbered("start", None, ClientMsg::StartGame { force: false }).await;
+    assert_eq!(ann.reply().await, Ok("start".into()), "answered as the first time, not refused as mid-game");
+    clients[0].assert_quiet().await;
+    ann.assert_quiet().await;
+}
+
+#[tokio::test]
+async fn the_public_rooms_cannot_be_made_private() {
+    let addr = start_server().await;
+    let mut mallory = Client::connect(addr, "Mallory").await;
+    mallory.send(ClientMsg::Join { name: "Mallory".into(), room: Some("colors".into()), token: None, private: Some(true), board: None }).await;
+    match mallory.recv().await {
+        ServerMsg::Error { message } => assert_eq!(message, "room is public"),
+        other => panic!("expected error, got {:?}", other),
+    }
+    let mut ann = Client::connect(addr, "Ann").await;
+    assert_eq!(ann.join("colors").await.players.len(), 1);
+
+    // Any other name still makes an invite-only room.
+    mallory.send(ClientMsg::Join { name: "Mallory".into(), room: Some("hideout".into()), token: None, private: Some(true), board: None }).await;
+    assert!(matches!(mallory.recv().await, ServerMsg::Welcome { .. }));
+    mallory.state().await;
+    ann.send(ClientMsg::Join { name: "Ann".into(), room: Some("hideout".into()), token: None, private: None, board: None }).await;
+    match ann.recv().await {
+        ServerMsg::Error { message } => assert_eq!(message, "room is private"),
+        other => panic!("expected error, got {:?}", other),
+    }
+}
+
+#[tokio::test]
+async fn public_rooms_keep_the_classic_board() {
+    let addr = start_server().await;
+    let join = |room: &str| ClientMsg::Join { name: "Ann".into(), room: Some(room.into()), token: None, private: None, board: Some("oklch-12x8".into()) };
+    let mut ann = Client::connect(addr, "Ann").await;
+    ann.send(join("colors")).await;
+    assert!(matches!(ann.recv().await, ServerMsg::Welcome { .. }));
+    assert_eq!(ann.state().await.board_id, "hsl-30x18", "the public room keeps the classic board");
+    ann.send(join("studio")).await;
+    assert!(matches!(ann.recv().await, ServerMsg::Welcome { .. }));
+    assert_eq!(ann.state().await.board_id, "oklch-12x8");
+}
+
+#[tokio::test]
+async fn other_tabs_and_namesakes_do_not_share_message_ids() {
+    let addr = start_server().await;
+    let (token, _) = register(addr, "ann").await;
+    // One account open in two tabs, and two guests who picked the same name.
+    let mut tabs = vec![Client::connect(addr, "Ann").await, Client::connect(addr, "Ann").await];
+    for tab in tabs.iter_mut() {
+        tab.join_as("tabs", Some(&token)).await;
+    }
+    tabs[0].state().await;
+    let mut namesakes = vec![Client::connect(addr, "Bob").await, Client::connect(addr, "Bob").await];
+    for bob in namesakes.iter_mut() {
+        bob.join("namesakes").await;
+    }
+    namesakes[0].state().await;
+
+    // Every one of them numbers its first message the same way, and every one is carried out.
+    for clients in [&mut tabs, &mut namesakes] {
+        for i in 0..2 {
+            clients[i].send_numbered("1", None, ClientMsg::SetReady { ready: true }).await;
+            let state = next_states(clients).await;
+            assert_eq!(clients[i].reply().await, Ok("1".into()));
+            assert_eq!(state.players.iter().filter(|p| p.ready).count(), i + 1);
+        }
+    }
+}
+
+#[tokio::test]
+async fn a_burst_of_logins_is_cut_off_before_the_hashing() {
+    let addr = start_server().await;
+    register(addr, "ivan").await;
+    let attempts = (0..20).map(|_| http(addr, "POST", "/api/auth/login", None, Some(serde_json::json!({"username": "ivan", "password": "nope"}))));
+    let statuses: Vec<u16> = futures_util::future::join_all(attempts).await.into_iter().map(|(status, _)| status).collect();
+    assert_eq!(statuses.iter().filter(|s| **s == 401).count(), 6, "{:?}", statuses);
+    assert_eq!(statuses.iter().filter(|s| **s == 429).count(), 14);
+}
+
+#[tokio::test]
+async fn only_refused_signups_count_against_an_address() {
+    let addr = start_server().await;
+    for i in 0..8 {
+        register(addr, &format!("neighbour{}", i)).await;
+    }
+    let taken = || Some(serde_json::json!({"username": "neighbour0", "password": "hunter22"}));
+    for _ in 0..6 {
+        assert_eq!(http(addr, "POST", "/api/auth/register", None, taken()).await.0, 400);
+    }
+    assert_eq!(http(addr, "POST", "/api/auth/register", None, taken()).await.0, 429);
+}
+
+/// The friends lists of `token`'s account, as usernames.
+async fn friend_lists(addr: SocketAddr, token: &str) -> serde_json::Value {
+    let (status, body) = http(addr, "GET", "/api/friends", Some(token), None).await;
+    assert_eq!(status, 200, "{}", body);
+    let names = |list: &serde_json::Value| list.as_array().unwrap().iter().map(|f| f["username"].clone()).collect::<Vec<_>>();
+    serde_json::json!({"friends": names(&body["friends"]), "incoming": names(&body["incoming"]), "outgoing": names(&body["outgoing"]), "blocked": names(&body["blocked"])})
+}
+
+#[tokio::test]
+async fn friend_requests_are_sent_declined_accepted_and_withdrawn() {
+    let addr = start_server().await;
+    let (ann, ann_id) = register(addr, "ann").await;
+    let (bob, bob_id) = register(addr, "bob").await;
+    let request = |username: &str| Some(serde_json::json!({"username": username}));
+
+    let (status, body) = http(addr, "POST", "/api/friends/requests", Some(&ann), request("Bob")).await;
+    assert_eq!((status, body["status"].as_str()), (200, Some("pending")));
+    assert_eq!(http(addr, "POST", "/api/friends/requests", Some(&ann), request("bob")).await.0, 409);
+    assert_eq!(http(addr, "POST", "/api/friends/requests", Some(&ann), request("nobody")).await.0, 404);
+    assert_eq!(http(addr, "POST", "/api/friends/requests", Some(&ann), request("ann")).await.0, 400);
+    assert_eq!(friend_lists(addr, &bob).await["incoming"], serde_json::json!(["ann"]));
+    assert_eq!(friend_lists(addr, &ann).await["outgoing"], serde_json::json!(["bob"]));
+
+    let decline = format!("/api/friends/requests/{}/decline", ann_id);
+    assert_eq!(http(addr, "POST", &decline, Some(&bob), None).await.0, 204);
+    assert_eq!(http(addr, "POST", &decline, Some(&bob), None).await.0, 404);
+    assert_eq!(friend_lists(addr, &ann).await["outgoing"], serde_json::json!([]));
+
+    // Asking back while a request is pending accepts it.
+    http(addr, "POST", "/api/friends/requests", Some(&ann), request("bob")).await;
+    let (status, body) = http(addr, "POST", "/api/friends/requests", Some(&bob), request("ann")).await;
+    assert_eq!((status, body["status"].as_str()), (200, Some("accepted")));
+    assert_eq!(friend_lists(addr, &ann).await, serde_json::json!({"friends": ["bob"], "incoming": [], "outgoing": [], "blocked": []}));
+    assert_eq!(http(addr, "POST", "/api/friends/requests", Some(&ann), request("bob")).await.0, 409);
+    assert_eq!(http(addr, "POST", &format!("/api/friends/requests/{}/accept", ann_id), Some(&bob), None).await.0, 404, "nothing left to accept");
+
+    assert_eq!(http(addr, "DELETE", &format!("/api/friends/{}", ann_id), Some(&bob), None).await.0, 204);
+    assert_eq!(http(addr, "DELETE", &format!("/api/friends/{}", bob_id), Some(&ann), None).await.0, 404);
+    assert_eq!(friend_lists(addr, &ann).await["friends"], serde_json::json!([]));
+}
+
+#[tokio::test]
+async fn blocking_ends_a_friendship_and_stops_requests_both_ways() {
+    let addr = start_server().await;
+    let (ann, ann_id) = register(addr, "ann").await;
+    let (bob, bob_id) = register(addr, "bob").await;
+    let request = |username: &str| Some(serde_json::json!({"username": username}));
+    http(addr, "POST", "/api/friends/requests", Some(&ann), request("bob")).await;
+    assert_eq!(http(addr, "POST", &format!("/api/friends/requests/{}/accept", ann_id), Some(&bob), None).await.0, 204);
+    assert_eq!(friend_lists(addr, &bob).await["friends"], serde_json::json!(["ann"]));
+
+    let block = format!("/api/friends/{}/block", bob_id);
+    assert_eq!(http(addr, "POST", &block, Some(&ann), None).await.0, 204);
+    assert_eq!(friend_lists(addr, &ann).await, serde_json::json!({"friends": [], "incoming": [], "outgoing": [], "blocked": ["bob"]}));
+    assert_eq!(friend_lists(addr, &bob).await, serde_json::json!({"friends": [], "incoming": [], "outgoing": [], "blocked": []}));
+    assert_eq!(http(addr, "POST", "/api/friends/requests", Some(&bob), request("ann")).await.0, 403);
+    assert_eq!(http(addr, "POST", "/api/friends/requests", Some(&ann), request("bob")).await.0, 403);
+    assert_eq!(http(addr, "POST", &format!("/api/friends/{}/block", Uuid::new_v4()), Some(&ann), None).await.0, 404);
+    assert_eq!(http(addr, "POST", &format!("/api/friends/{}/block", ann_id), Some(&ann), None).await.0, 400);
+
+    assert_eq!(http(addr, "DELETE", &block, Some(&ann), None).await.0, 204);
+    assert_eq!(http(addr, "DELETE", &block, Some(&ann), None).await.0, 404);
+    assert_eq!(http(addr, "POST", "/api/friends/requests", Some(&bob), request("ann")).await.1["status"], "pending");
+}
+
+#[tokio::test]
+async fn the_friend_flag_is_worked_out_per_viewer() {
+    let addr = start_server().await;
+    let (ann, ann_id) = register(addr, "ann").await;
+    let (bob, bob_id) = register(addr, "bob").await;
+    let (cid, cid_id) = register(addr, "cid").await;
+    http(addr, "POST", "/api/friends/requests", Some(&ann), Some(serde_json::json!({"username": "bob"}))).await;
+    http(addr, "POST", &format!("/api/friends/requests/{}/accept", ann_id), Some(&bob), None).await;
+
+    let mut clients = [Client::connect(addr, "Ann").await, Client::connect(addr, "Bob").await, Client::connect(addr, "Cid").await];
+    for (i, token) in [&ann, &bob, &cid].into_iter().enumerate() {
+        clients[i].join_as("club", Some(token)).await;
+    }
+    // One more broadcast that reaches everyone; friends may hear about each other's presence first.
+    clients[2].send(ClientMsg::SetReady { ready: true }).await;
+    let mut flags = Vec::new();
+    for c in clients.iter_mut() {
+        let state = loop {
+            match c.recv().await {
+                ServerMsg::State { state } if state.players.iter().any(|p| p.ready) => break state,
+                ServerMsg::State { .. } | ServerMsg::Presence { .. } | ServerMsg::PresenceUpdate { .. } => {}
+                other => panic!("{}: unexpected {:?}", c.name, other),
+            }
+        };
+        let friend_of = |user: Uuid| state.players.iter().find(|p| p.user_id == Some(user)).unwrap().friend;
+        flags.push([friend_of(ann_id), friend_of(bob_id), friend_of(cid_id)]);
+    }
+    assert_eq!(flags, vec![[false, true, false], [true, false, false], [false, false, false]]);
+}
+
+#[tokio::test]
+async fn deleting_an_account_leaves_nothing_behind() {
+    let addr = start_server().await;
+    let olga = Some(serde_json::json!({"username": "olga", "password": "old-secret"}));
+    assert_eq!(http(addr, "POST", "/api/auth/import-legacy", None, olga.clone()).await.0, 201);
+    let (_, body) = http(addr, "POST", "/api/auth/login", None, olga.clone()).await;
+    let token = body["token"].as_str().unwrap().to_string();
+    let mut png = Vec::new();
+    image::RgbImage::new(8, 8).write_to(&mut std::io::Cursor::new(&mut png), image::ImageFormat::Png).unwrap();
+    let (status, body) = http_raw(addr, "PUT", "/api/me/avatar", Some(&token), "image/png", &png).await;
+    assert_eq!(status, 200, "{}", body);
+    let avatar = body["avatar"].as_str().unwrap().to_string();
+    assert_eq!(http(addr, "GET", &avatar, None, None).await.0, 200);
+
+    let mut clients = [Client::connect(addr, "Olga").await, Client::connect(addr, "Bob").await];
+    clients[0].join_as("wake", Some(&token)).await;
+    clients[1].join("wake").await;
+    clients[0].state().await;
+
+    assert_eq!(http(addr, "DELETE", "/api/me", Some(&token), Some(serde_json::json!({"password": "old-secret"}))).await.0, 204);
+    let state = clients[1].state().await;
+    assert_eq!(state.players.iter().map(|p| p.name.as_str()).collect::<Vec<_>>(), vec!["Bob"], "the account's seat is gone");
+    assert_eq!(http(addr, "GET", &avatar, None, None).await.0, 404, "the uploaded picture is no longer served");
+    assert_eq!(http(addr, "POST", "/api/auth/import-legacy", None, olga).await.0, 409, "the legacy name stays imported");
+}
+
+#[tokio::test]
+async fn a_rename_reaches_the_seats_taken_under_the_old_name() {
+    let addr = start_server().await;
+    let (token, _) = register(addr, "ann").await;
+    let mut clients = vec![Client::connect(addr, "ann").await, Client::connect(addr, "Bob").await];
+    clients[0].join_as("names", Some(&token)).await;
+    clients[1].join("names").await;
+    clients[0].state().await;
+
+    let (status, body) = http(addr, "PUT", "/api/me/username", Some(&token), Some(serde_json::json!({"username": "anna"}))).await;
+    assert_eq!(status, 200, "{}", body);
+    let state = next_states(&mut clients).await;
+    let ann = state.players.iter().find(|p| p.id == clients[0].id).unwrap();
+    assert_eq!((ann.name.as_str(), ann.avatar.as_deref()), ("anna", Some("/api/avatars/anna.svg")));
+}
+
+#[tokio::test]
+async fn a_short_new_password_gets_the_same_error_as_at_signup() {
+    let addr = start_server().await;
+    let (token, _) = register(addr, "pat").await;
+    let (status, body) = http(addr, "PUT", "/api/me/password", Some(&token), Some(serde_json::json!({"old_password": "hunter22", "new_password": "abc"}))).await;
+    assert_eq!((status, body["error"].as_str()), (400, Some("password_too_short")));
+    let (status, body) = http(addr, "POST", "/api/auth/register", None, Some(serde_json::json!({"username": "sam", "password": "abc"}))).await;
+    assert_eq!((status, body["error"].as_str()), (400, Some("password_too_short")));
+}
+
+#[tokio::test]
+async fn avatar_uploads_are_checked_before_decoding() {
+    let addr = start_server().await;
+    let (token, _) = register(addr, "ava").await;
+    let (status, body) = http_raw(addr, "PUT", "/api/me/avatar", Some(&token), "text/plain", b"hello").await;
+    assert_eq!((status, body["error"].as_str()), (415, Some("unsupported_image")));
+    let (status, _) = http_raw(addr, "PUT", "/api/me/avatar", Some(&token), "image/png", &vec![0; 4 * 1024 * 1024 + 1]).await;
+    assert_eq!(status, 413);
+    let (status, body) = http_raw(addr, "PUT", "/api/me/avatar", Some(&token), "image/png", b"\x89PNG\r\n\x1a\nnot really").await;
+    assert_eq!((status, body["error"].as_str()), (400, Some("invalid_image")));
+}
+
+/// Every series the README lists, once something has happened to fill the labelled ones.
+const METRIC_SERIES: &[&str] = &[
+    "keldurben_ws_connections",
+    "keldurben_rooms{",
+    "keldurben_ws_messages_in_total{",
+    "keldurben_ws_messages_out_total{",
+    "keldurben_broadcast_fanout_count",
+    "keldurben_auth_attempts_total{",
+    "keldurben_db_query_duration_seconds_count{",
+    "keldurben_ws_handler_duration_seconds_count{",
+];
+
+#[tokio::test]
+async fn metrics_cover_the_documented_series() {
+    let addr = start_server().await;
+    register(addr, "mira").await;
+    let mut alice = Client::connect(addr, "alice").await;
+    alice.join("default").await;
+
+    let (status, text) = http_text(addr, "GET", "/metrics", None, "text/plain", b"").await;
+    assert_eq!(status, 200);
+    for series in METRIC_SERIES {
+        assert!(text.contains(series), "{} missing from\n{}", series, text);
+    }
+    assert!(text.contains(r#"keldurben_rooms{game="hues_and_cues",phase="lobby"}"#), "{}", text);
+    assert!(text.contains(r#"keldurben_auth_attempts_total{kind="register",result="success"}"#), "{}", text);
+}
+
+#[tokio::test]
+async fn metrics_bind_takes_metrics_off_the_public_port() {
+    let (addr, metrics_addr, _db) = start_server_with(true).await;
+    let metrics_addr = metrics_addr.unwrap();
+    register(addr, "mira").await;
+
+    let (status, text) = http_text(addr, "GET", "/metrics", None, "text/plain", b"").await;
+    assert_eq!(status, 404, "public /metrics answered {}", text);
+    let (status, text) = http_text(metrics_addr, "GET", "/metrics", None, "text/plain", b"").await;
+    assert_eq!(status, 200);
+    assert!(text.contains("keldurben_auth_attempts_total{"), "{}", text);
+    let (status, _) = http(metrics_addr, "GET", "/healthz", None, None).await;
+    assert_eq!(status, 404, "the metrics listener serves nothing else");
+}
+
+#[tokio::test]
+async fn readyz_reports_a_lost_database() {
+    let (addr, _, db) = start_server_with(false).await;
+    let (status, body) = http(addr, "GET", "/readyz", None, None).await;
+    assert_eq!(status, 200, "{}", body);
+    assert_eq!(body["status"], "ready");
+
+    db.close().await;
+    let (status, body) = http(addr, "GET", "/readyz", None, None).await;
+    assert_eq!(status, 503, "{}", body);
+    assert_eq!(body["status"], "not_ready");
+    assert_eq!(body["checks"]["database"]["ok"], false);
+    assert!(body["checks"]["database"]["error"].as_str().is_some_and(|e| !e.is_empty()), "{}", body);
+    assert_eq!(body["checks"]["migrations"]["ok"], false);
+    assert_eq!(body["checks"]["hub"]["ok"], true, "only the database is down");
+    let (status, _) = http(addr, "GET", "/healthz", None, None).await;
+    assert_eq!(status, 200, "liveness does not depend on the database");
+}